@@ -0,0 +1,230 @@
+use std::fmt::Write;
+
+use nalgebra::Point3;
+
+use crate::slicer::{
+    arc::{fit_arcs, ArcSettings, Fit},
+    toolpath::ToolPath,
+    FloatValue,
+};
+
+/// Speed of the filament while it is retracted or primed, in mm/s
+const RETRACT_SPEED: FloatValue = 40.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GcodeOptions {
+    pub layer_height: FloatValue,
+    pub filament_diameter: FloatValue,
+    /// Print speed in mm/s
+    pub print_speed: FloatValue,
+    /// Travel speed in mm/s
+    pub travel_speed: FloatValue,
+    pub z_hop: FloatValue,
+    /// Filament retracted before each tool change and primed again after it, in mm
+    pub tool_change_retract: FloatValue,
+    /// Replaces segments lying on a circle with G2 and G3 moves
+    pub arc_fitting: Option<ArcSettings>,
+}
+
+/// Writes the paths in order, using relative extrusion.
+///
+/// The extruded volume of each segment is scaled by the width and flow of its path,
+/// so non-planar segments compensate for the varying gap below them.
+/// Tools are changed before the first path of each extruder, retracting the filament of the
+/// previous one first so it doesn't ooze while parked. The purge is up to the paths.
+pub fn generate_gcode<'a, I>(paths: I, options: &GcodeOptions) -> String
+where
+    I: IntoIterator<Item = &'a ToolPath>,
+{
+    let filament_area = std::f64::consts::PI * (options.filament_diameter / 2.0).powi(2);
+    let print_feedrate = options.print_speed * 60.0;
+    let travel_feedrate = options.travel_speed * 60.0;
+
+    let mut gcode = String::new();
+    writeln!(gcode, "; generated by bampy").unwrap();
+    writeln!(gcode, "G21 ; millimeters").unwrap();
+    writeln!(gcode, "G90 ; absolute positioning").unwrap();
+    writeln!(gcode, "M83 ; relative extrusion").unwrap();
+
+    let mut position: Option<Point3<FloatValue>> = None;
+    let mut extruder = None;
+    for path in paths {
+        let Some(start) = path.points.first() else {
+            continue;
+        };
+        if extruder != Some(path.extruder) {
+            let retract = extruder.is_some() && options.tool_change_retract > 0.0;
+            if retract {
+                writeln!(
+                    gcode,
+                    "G1 E{:.5} F{:.0} ; retract before the tool change",
+                    -options.tool_change_retract,
+                    RETRACT_SPEED * 60.0
+                )
+                .unwrap();
+            }
+            writeln!(gcode, "T{}", path.extruder).unwrap();
+            if retract {
+                writeln!(
+                    gcode,
+                    "G1 E{:.5} F{:.0} ; prime the new tool",
+                    options.tool_change_retract,
+                    RETRACT_SPEED * 60.0
+                )
+                .unwrap();
+            }
+            extruder = Some(path.extruder);
+        }
+        if let Some(current) = position {
+            let z = current.z.max(start.z) + options.z_hop;
+            writeln!(gcode, "G0 Z{:.3} F{:.0}", z, travel_feedrate).unwrap();
+            writeln!(gcode, "G0 X{:.3} Y{:.3}", start.x, start.y).unwrap();
+            writeln!(gcode, "G0 Z{:.3}", start.z).unwrap();
+        } else {
+            writeln!(
+                gcode,
+                "G0 X{:.3} Y{:.3} Z{:.3} F{:.0}",
+                start.x, start.y, start.z, travel_feedrate
+            )
+            .unwrap();
+        }
+
+        writeln!(gcode, "G1 F{:.0}", print_feedrate).unwrap();
+        let extrusion_area = path.width * options.layer_height;
+        let extrusions = path
+            .points
+            .windows(2)
+            .zip(path.flow.iter())
+            .map(|(segment, flow)| {
+                (segment[1] - segment[0]).norm() * extrusion_area * flow / filament_area
+            })
+            .collect::<Vec<_>>();
+        let fits = match &options.arc_fitting {
+            Some(settings) => fit_arcs(&path.points[..=extrusions.len()], settings),
+            None => (0..extrusions.len())
+                .map(|start| Fit {
+                    start,
+                    end: start + 1,
+                    arc: None,
+                })
+                .collect(),
+        };
+        for fit in fits {
+            // Arcs extrude as much as the segments they replace
+            let extrusion = extrusions[fit.start..fit.end].iter().sum::<FloatValue>();
+            let end = path.points[fit.end];
+            match fit.arc {
+                Some(arc) => {
+                    let offset = arc.center - path.points[fit.start].xy();
+                    writeln!(
+                        gcode,
+                        "{} X{:.3} Y{:.3} Z{:.3} I{:.3} J{:.3} E{:.5}",
+                        if arc.clockwise { "G2" } else { "G3" },
+                        end.x,
+                        end.y,
+                        end.z,
+                        offset.x,
+                        offset.y,
+                        extrusion
+                    )
+                    .unwrap();
+                }
+                None => writeln!(
+                    gcode,
+                    "G1 X{:.3} Y{:.3} Z{:.3} E{:.5}",
+                    end.x, end.y, end.z, extrusion
+                )
+                .unwrap(),
+            }
+        }
+        position = path.points.last().copied();
+    }
+
+    gcode
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::point;
+
+    use crate::{
+        gcode::{generate_gcode, GcodeOptions},
+        slicer::toolpath::ToolPath,
+    };
+
+    fn extrusions(gcode: &str) -> Vec<f64> {
+        gcode
+            .lines()
+            .filter(|line| line.starts_with("G1 X"))
+            .map(|line| line.rsplit_once(" E").unwrap().1.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_generate_gcode() {
+        let options = GcodeOptions {
+            layer_height: 0.2,
+            filament_diameter: 1.75,
+            print_speed: 40.0,
+            travel_speed: 150.0,
+            z_hop: 0.4,
+            tool_change_retract: 2.0,
+            arc_fitting: None,
+        };
+        let path = ToolPath {
+            width: 0.4,
+            flow: vec![1.0, 0.5],
+            ..ToolPath::planar(
+                vec![
+                    point![0.0, 0.0, 0.2],
+                    point![10.0, 0.0, 0.2],
+                    point![10.0, 10.0, 0.2],
+                ],
+                1.0,
+                false,
+            )
+        };
+        let gcode = generate_gcode([&path], &options);
+
+        // Each segment extrudes its length times the extrusion area and flow, in filament length
+        let filament_area = std::f64::consts::PI * (1.75f64 / 2.0).powi(2);
+        let full = 10.0 * 0.4 * 0.2 / filament_area;
+        let e = extrusions(&gcode);
+        assert_eq!(e.len(), 2);
+        assert_relative_eq!(e[0], full, epsilon = 1e-5);
+        assert_relative_eq!(e[1], full / 2.0, epsilon = 1e-5);
+        assert!(gcode.contains("M83"));
+        assert!(gcode.contains("T0"));
+    }
+
+    #[test]
+    fn test_tool_change() {
+        let options = GcodeOptions {
+            layer_height: 0.2,
+            filament_diameter: 1.75,
+            print_speed: 40.0,
+            travel_speed: 150.0,
+            z_hop: 0.4,
+            tool_change_retract: 2.0,
+            arc_fitting: None,
+        };
+        let path = |extruder: usize| ToolPath {
+            extruder,
+            ..ToolPath::planar(
+                vec![point![0.0, 0.0, 0.2], point![10.0, 0.0, 0.2]],
+                1.0,
+                false,
+            )
+        };
+        let gcode = generate_gcode([&path(0), &path(1)], &options);
+        let changes = gcode
+            .lines()
+            .filter(|line| line.starts_with('T') || line.starts_with("G1 E"))
+            .map(|line| line.split(" F").next().unwrap())
+            .collect::<Vec<_>>();
+
+        // The first tool is only selected, the filament is still loaded
+        assert_eq!(changes, vec!["T0", "G1 E-2.00000", "T1", "G1 E2.00000"]);
+    }
+}
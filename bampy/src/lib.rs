@@ -1,90 +1,694 @@
-#![feature(extract_if)]
-use std::collections::VecDeque;
+#![cfg_attr(test, feature(test))]
+use std::collections::{HashSet, VecDeque};
 
 use approx::relative_eq;
-use nalgebra::{point, vector, Vector3};
+use gcode::{generate_gcode, GcodeOptions};
+use js_sys::{Function, Int32Array};
+use nalgebra::{point, vector, Affine3, Matrix4, Point2, Point3, Translation3, Vector3};
 use num::Float;
-use result::{Slice, SliceOptions, SliceResult};
-use slicer::{axis::Axis, slice_path::SlicePath, trace_surface::trace_surface};
-use wasm_bindgen::prelude::wasm_bindgen;
+use progress::Progress;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use result::{
+    BrimOptions, LayerHeights, ModifierOptions, ModifierVolume, Orientation, OrientationOptions,
+    OrientationResult, PaintOptions, PlacementOptions, PlateMode, SkirtOptions, Slice,
+    SliceCollision, SliceError, SliceGap, SliceOptions, SliceResult, SliceStage, SupportMode,
+};
+use slicer::{
+    adhesion::{brim, first_layer_hull, raft, skirt, RaftSettings},
+    arc::ArcSettings,
+    axis::Axis,
+    base_slices::Gap,
+    bridge::generate_bridges,
+    extrusion::segment_flow,
+    frame::{build_rotation, BuildFrame},
+    job::{with_signal, Cancelled, Job},
+    layer_heights::{adaptive_layer_positions, profile_layer_positions},
+    material::{
+        group_extruders, insert_wipe_tower, split_by_extruder, split_walls_by_extruder,
+        WipeTowerSettings,
+    },
+    modifier::{modify_flow, non_planar_override, ring_perimeters, Modifier, Volume},
+    orientation::OrientationSettings,
+    paint::{decode_paint, Paint},
+    path_order::{optimise_order, travel_distance},
+    perimeters::inner_walls,
+    plate::{overlapping, sequential_collisions, wipe_tower_collisions, Toolhead},
+    polygon::offset,
+    sdf::{Sdf, Sdf3dModifiers, SdfBox, SdfCylinder, SdfSphere},
+    seam::SeamPlacer,
+    simplify::{simplify, SimplifySettings},
+    slice_path::SlicePath,
+    support::{generate_support, needs_support, SupportPattern, SupportSettings},
+    toolpath::{PathRole, ToolPath},
+    trace_surface::{max_reachable_angle, trace_surface},
+    tree_support::generate_tree_support,
+};
+use tsify::Tsify;
+use wasm_bindgen::{prelude::*, JsCast};
 
-use crate::slicer::{mesh::Mesh, split_surface::split_surface, triangle::Triangle, FloatValue};
+use crate::slicer::{
+    mesh::Mesh,
+    split_surface::{has_min_area, is_top_surface, split_surface, SplitSettings},
+    triangle::Triangle,
+    FloatValue,
+};
 
+mod gcode;
+mod progress;
 mod result;
 mod slicer;
 mod util;
 
+#[cfg(feature = "wasm-threads")]
+pub use wasm_bindgen_rayon::init_thread_pool;
+
 const BED_NORMAL: Vector3<f64> = vector![0f64, 0f64, 1f64];
 
+fn triangle_key(triangle: &Triangle) -> [u64; 9] {
+    let mut key = [0; 9];
+    for (i, point) in [triangle.a, triangle.b, triangle.c].iter().enumerate() {
+        for axis in 0..3 {
+            key[i * 3 + axis] = point[axis].to_bits();
+        }
+    }
+    key
+}
+
+fn triangles_from_positions(positions: &[f32]) -> Vec<Triangle> {
+    assert_eq!(positions.len() % 9, 0);
+    positions
+        .chunks_exact(9)
+        .map(|chunk| {
+            let point = |i: usize| {
+                point![
+                    chunk[i] as FloatValue,
+                    chunk[i + 1] as FloatValue,
+                    chunk[i + 2] as FloatValue
+                ]
+            };
+            Triangle::new(point(0), point(3), point(6))
+        })
+        .collect()
+}
+
+/// Decodes the paint of each triangle, triangles without paint keep the default attributes
+fn paint_triangles(triangles: &mut [Triangle], paint: &PaintOptions) {
+    let state = |paint: &[String], i: usize| paint.get(i).map_or(0, |data| decode_paint(data));
+    for (i, triangle) in triangles.iter_mut().enumerate() {
+        let attributes = &mut triangle.attributes;
+        attributes.seam = state(&paint.seam, i).into();
+        attributes.support = state(&paint.support, i).into();
+        attributes.non_planar = state(&paint.non_planar, i).into();
+        // 3MF files count extruders from 1, leaving 0 unpainted
+        attributes.extruder = state(&paint.extruder, i).checked_sub(1);
+    }
+}
+
+/// Moves the model into the bed frame, in the order the placement options are listed
+fn place_mesh(
+    mut mesh: Mesh,
+    build_direction: &Vector3<FloatValue>,
+    placement: &PlacementOptions,
+) -> Result<(Mesh, BuildFrame), SliceError> {
+    let singular =
+        || SliceError::InvalidOptions("the placement of the model can't be undone".to_string());
+    let mut frame = BuildFrame::default();
+    if let Some(transform) = placement.transform {
+        frame = frame
+            .then(&Affine3::from_matrix_unchecked(Matrix4::from_column_slice(
+                &transform,
+            )))
+            .ok_or_else(singular)?;
+    }
+    let rotation = build_rotation(build_direction).ok_or_else(|| {
+        SliceError::InvalidOptions(format!(
+            "the build direction {:?} has no length",
+            build_direction.as_slice()
+        ))
+    })?;
+    frame = frame
+        .then(&nalgebra::convert(rotation))
+        .ok_or_else(singular)?;
+    mesh.transform(frame.transform());
+
+    if let Some(face) = placement.lay_flat_face {
+        let rotation = mesh.lay_flat(face).ok_or_else(|| {
+            SliceError::InvalidOptions(format!(
+                "face {} can't be laid flat, the model has {} faces",
+                face,
+                mesh.triangles.len()
+            ))
+        })?;
+        let rotation = nalgebra::convert(rotation);
+        mesh.transform(&rotation);
+        frame = frame.then(&rotation).ok_or_else(singular)?;
+    }
+    let drop_to_bed = placement
+        .drop_to_bed
+        .unwrap_or_else(|| !relative_eq!(build_direction.normalize(), Vector3::z()));
+    if drop_to_bed || placement.center.is_some() {
+        let translation = nalgebra::convert(mesh.bed_placement(placement.center.map(Point2::from)));
+        mesh.transform(&translation);
+        frame = frame.then(&translation).ok_or_else(singular)?;
+    }
+    Ok((mesh, frame))
+}
+
+/// Places a modifier along with the model it belongs to
+fn place_modifier(options: &ModifierOptions, frame: &BuildFrame) -> Modifier {
+    fn sdf<T>(sdf: T, center: &[f64; 3], frame: &BuildFrame) -> Volume
+    where
+        T: Sdf<3> + Send + Sync + 'static,
+    {
+        let to_bed = frame.transform() * Translation3::from(Vector3::from(*center));
+        Volume::Sdf(Box::new(sdf.transform(to_bed)))
+    }
+    let volume = match &options.volume {
+        ModifierVolume::Mesh { positions } => {
+            let mut mesh = Mesh::from(triangles_from_positions(positions));
+            mesh.transform(frame.transform());
+            Volume::Mesh(mesh)
+        }
+        ModifierVolume::Sphere { center, radius } => sdf(SdfSphere::new(*radius), center, frame),
+        ModifierVolume::Box { center, size } => sdf(
+            SdfBox::new((Vector3::from(*size) / 2.0).into()),
+            center,
+            frame,
+        ),
+        ModifierVolume::Cylinder {
+            center,
+            radius,
+            height,
+        } => sdf(SdfCylinder::new(*radius, *height), center, frame),
+    };
+    Modifier {
+        volume,
+        settings: options.into(),
+    }
+}
+
+#[wasm_bindgen]
+extern "C" {
+    /// Called with every [`SliceProgress`] event of [`slice_streaming`]
+    #[wasm_bindgen(typescript_type = "(event: SliceProgress) => void")]
+    pub type SliceProgressCallback;
+}
+
+#[wasm_bindgen]
+pub fn slice(options: SliceOptions) -> Result<SliceResult, JsError> {
+    SliceJob::new(None).slice(options, None)
+}
+
+/// Slices like [`slice`], reporting progress and resolved levels while it runs
+#[wasm_bindgen]
+pub fn slice_streaming(
+    options: SliceOptions,
+    callback: SliceProgressCallback,
+) -> Result<SliceResult, JsError> {
+    SliceJob::new(None).slice(options, Some(callback))
+}
+
+/// Finds the build directions with the most non-planar surface area and the least overhang
+#[wasm_bindgen]
+pub fn optimise_orientation(options: OrientationOptions) -> Result<OrientationResult, JsError> {
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    orientation_with_job(options, &Job::new()).map_err(JsError::from)
+}
+
+fn orientation_with_job(
+    options: OrientationOptions,
+    job: &Job,
+) -> Result<OrientationResult, SliceError> {
+    options.validate()?;
+    let triangles = triangles_from_positions(&options.positions);
+    let settings = OrientationSettings {
+        max_angle: options.max_angle,
+        min_surface_area: std::f64::consts::PI * (options.nozzle_diameter / 2.0).powi(2),
+        max_overhang_angle: options.max_overhang_angle,
+        overhang_weight: options.overhang_weight,
+        bed_tolerance: options.nozzle_diameter,
+        split: options.surfaces.into(),
+    };
+    let scores = slicer::orientation::optimise_orientation(
+        &triangles,
+        options.samples,
+        options.results,
+        &settings,
+        job,
+    )?;
+    Ok(OrientationResult {
+        orientations: scores.into_iter().map(Orientation::from).collect(),
+    })
+}
+
+/// A slice that can be cancelled while it runs
 #[wasm_bindgen]
-pub fn slice(
+pub struct SliceJob {
+    job: Job,
+    signal: Option<Int32Array>,
+}
+
+#[wasm_bindgen]
+impl SliceJob {
+    /// The job is also cancelled once the first element of `signal` is set to a non-zero value.
+    /// Backed by a `SharedArrayBuffer`, this allows cancelling it from another thread.
+    #[wasm_bindgen(constructor)]
+    pub fn new(signal: Option<Int32Array>) -> Self {
+        Self {
+            job: Job::new(),
+            signal,
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.job.cancel();
+    }
+
+    /// A view of the cancel flag of the job, set its first element to a non-zero value to cancel it.
+    /// Unlike the `signal` passed to the constructor, workers see it while they slice in parallel.
+    #[cfg(feature = "wasm-threads")]
+    #[wasm_bindgen(getter)]
+    pub fn signal(&self) -> Int32Array {
+        let memory = wasm_bindgen::memory().unchecked_into::<js_sys::WebAssembly::Memory>();
+        let index = self.job.flag() as *const _ as u32 / 4;
+        Int32Array::new(&memory.buffer()).subarray(index, index + 1)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cancelled(&self) -> bool {
+        with_signal(self.signal.clone(), || self.job.is_cancelled())
+    }
+
+    /// Throws if the job is cancelled before it finishes
+    pub fn slice(
+        &self,
+        options: SliceOptions,
+        callback: Option<SliceProgressCallback>,
+    ) -> Result<SliceResult, JsError> {
+        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+        let mut progress = match callback {
+            Some(callback) => {
+                let callback = callback.unchecked_into::<Function>();
+                Progress::new(move |event| {
+                    callback
+                        .call1(&JsValue::NULL, &event.into_js().unwrap_throw())
+                        .unwrap_throw();
+                })
+            }
+            None => Progress::none(),
+        };
+        with_signal(self.signal.clone(), || {
+            slice_with_progress(options, &self.job, &mut progress)
+        })
+        .map_err(JsError::from)
+    }
+}
+
+/// Simplifies a finished level and adds it to the result, skipping empty ones
+fn resolve_level(
+    levels: &mut Vec<Vec<ToolPath>>,
+    level: Vec<ToolPath>,
+    settings: &SimplifySettings,
+    progress: &mut Progress,
+) {
+    if !level.is_empty() {
+        let level = level
+            .into_iter()
+            .map(|path| simplify(path, settings))
+            .collect::<Vec<_>>();
+        progress.level(levels.len(), &level);
+        levels.push(level);
+    }
+}
+
+/// The paths of one object in the bed frame, before the order they are printed in is known
+struct ObjectPaths {
+    /// Non-planar surfaces, lowest first, with their perimeters and fill
+    surfaces: Vec<(Mesh, Vec<ToolPath>, Vec<ToolPath>)>,
+    /// Walls, support and bridges by layer, with their role and extruder
+    walls: Vec<(PathRole, usize, SlicePath)>,
+    gaps: Vec<Gap>,
+    /// Overhangs the tree support couldn't reach from the bed or the model
+    unsupported: Vec<Point3<FloatValue>>,
+    layers: Vec<FloatValue>,
+    modifiers: Vec<Modifier>,
+    /// Height of the bottom of the object
+    bottom: FloatValue,
+    layer_height: FloatValue,
+    nozzle_diameter: FloatValue,
+    max_angle: FloatValue,
+    extruder: usize,
+    /// Flow is relative to the layer height the G-code is generated with
+    flow_scale: FloatValue,
+}
+
+/// Settings of the plate shared by the objects printed together
+struct PlateSettings {
+    skirt: SkirtOptions,
+    brim: BrimOptions,
+    raft: RaftSettings,
+    layer_height: FloatValue,
+    nozzle_diameter: FloatValue,
+    simplify: SimplifySettings,
+}
+
+pub fn slice_with_progress(
+    mut options: SliceOptions,
+    job: &Job,
+    progress: &mut Progress,
+) -> Result<SliceResult, SliceError> {
+    options.validate()?;
+    let gcode_options = GcodeOptions {
+        layer_height: options.layer_height,
+        filament_diameter: options.filament_diameter,
+        print_speed: options.print_speed,
+        travel_speed: options.travel_speed,
+        z_hop: options.z_hop,
+        tool_change_retract: options.tool_change_retract,
+        arc_fitting: options.arc_fitting.map(ArcSettings::from),
+    };
+    let build_direction = options.build_direction.into();
+    let mode = options.plate;
+    let optimise_path_order = options.optimise_path_order;
+    let wipe_tower = options.wipe_tower.map(|tower| WipeTowerSettings {
+        position: tower.position.into(),
+        width: tower.width,
+        depth: tower.depth,
+        purge_volume: tower.purge_volume,
+        line_width: options.nozzle_diameter,
+        layer_height: options.layer_height,
+    });
+    let plate_settings = PlateSettings {
+        skirt: options.skirt,
+        brim: options.brim,
+        raft: RaftSettings {
+            base_layers: options.raft.base_layers,
+            interface_layers: options.raft.interface_layers,
+            margin: options.raft.margin,
+            base_spacing: options.raft.base_spacing,
+            interface_spacing: options.raft.interface_spacing,
+            gap: options.raft.gap,
+        },
+        layer_height: options.layer_height,
+        nozzle_diameter: options.nozzle_diameter,
+        simplify: SimplifySettings::from(options.simplify),
+    };
+    let objects = std::mem::take(&mut options.objects);
+    let plate = !objects.is_empty();
+    let objects = if plate {
+        objects
+            .into_iter()
+            .map(|object| object.options(&options))
+            .collect::<Vec<_>>()
+    } else {
+        vec![options]
+    };
+    for object in &objects {
+        object.validate()?;
+    }
+
+    let placed = objects
+        .into_iter()
+        .map(|mut object| {
+            let positions = std::mem::take(&mut object.positions);
+            let mut triangles = triangles_from_positions(&positions);
+            paint_triangles(&mut triangles, &object.paint);
+            let (mesh, frame) =
+                place_mesh(Mesh::from(triangles), &build_direction, &object.placement)?;
+            Ok((object, mesh, frame))
+        })
+        .collect::<Result<Vec<_>, SliceError>>()?;
+    let bounds = placed
+        .iter()
+        .map(|(_, mesh, _)| mesh.aabb)
+        .collect::<Vec<_>>();
+    let toolhead = match mode {
+        PlateMode::AllAtOnce => None,
+        PlateMode::Sequential {
+            toolhead,
+            gantry_height,
+        } => Some(Toolhead {
+            min: vector![toolhead[0], toolhead[1]],
+            max: vector![toolhead[2], toolhead[3]],
+            gantry_height,
+        }),
+    };
+    let mut collisions = match &toolhead {
+        None => overlapping(&bounds),
+        Some(toolhead) => sequential_collisions(&bounds, toolhead),
+    };
+    for collision in &collisions {
+        console_log!("Collision on the plate: {:?}", collision);
+    }
+
+    // The preview of a single model lines up with the model, plates are shown on the bed
+    let frame = if plate {
+        BuildFrame::default()
+    } else {
+        placed[0].2
+    };
+    progress.set_frame(frame);
+
+    let mut objects = Vec::new();
+    for (object, mesh, object_frame) in placed {
+        let flow_scale = object.layer_height / gcode_options.layer_height;
+        let modifiers = object
+            .modifiers
+            .iter()
+            .map(|modifier| place_modifier(modifier, &object_frame))
+            .collect::<Vec<_>>();
+        // Surface ids are unique across the plate
+        let first_surface = objects
+            .iter()
+            .map(|object: &ObjectPaths| object.surfaces.len())
+            .sum();
+        objects.push(slice_object(
+            object,
+            mesh,
+            modifiers,
+            flow_scale,
+            first_surface,
+            job,
+            progress,
+        )?);
+    }
+    // Objects printed all at once share their layers, sequential ones are finished one by one
+    let groups = match mode {
+        PlateMode::AllAtOnce => vec![objects],
+        PlateMode::Sequential { .. } => objects.into_iter().map(|object| vec![object]).collect(),
+    };
+    let mut levels = Vec::new();
+    let mut gaps = Vec::new();
+    let mut unsupported = Vec::new();
+    for group in groups {
+        let (group_gaps, group_unsupported) =
+            resolve_dependencies(group, &plate_settings, &mut levels, job, progress)?;
+        gaps.extend(group_gaps);
+        unsupported.extend(group_unsupported);
+    }
+    let travel_before = travel_distance(levels.iter().flatten());
+    let levels = if optimise_path_order {
+        console_log!("Optimising Path Order");
+        progress.stage(SliceStage::PathOrder);
+        optimise_order(levels)
+    } else {
+        levels
+    };
+    // Only the reordering counts as saved, grouping by extruder may add travel
+    let travel_saved = travel_before - travel_distance(levels.iter().flatten());
+    // Tool changes take longer than any travel they save
+    let levels = group_extruders(levels);
+    let travel = travel_distance(levels.iter().flatten());
+    console_log!(
+        "Travel distance {:.1}mm, saved {:.1}mm",
+        travel,
+        travel_saved
+    );
+
+    let levels = match wipe_tower {
+        Some(settings) => {
+            let (levels, height) = insert_wipe_tower(levels, &settings);
+            // Without tool changes there is no tower to collide with
+            if height > 0.0 {
+                let tower = settings.bounds(height);
+                for collision in wipe_tower_collisions(&bounds, &tower, toolhead.as_ref()) {
+                    console_log!("Collision on the plate: {:?}", collision);
+                    collisions.push(collision);
+                }
+            }
+            levels
+        }
+        None => levels,
+    };
+
+    console_log!("Generating G-code");
+    progress.stage(SliceStage::Gcode);
+    let gcode = generate_gcode(levels.iter().flatten(), &gcode_options);
+    let mut slices = Vec::new();
+    let mut position = None;
+    for (dependency, level) in levels.into_iter().enumerate() {
+        for path in level {
+            if path.points.is_empty() {
+                continue;
+            }
+            if let Some(position) = position {
+                slices.push(Slice::new(
+                    frame.path_to_model(ToolPath {
+                        role: PathRole::Travel,
+                        layer: path.layer,
+                        ..ToolPath::planar(vec![position, path.points[0]], 0.0, false)
+                    }),
+                    dependency,
+                ));
+            }
+            position = path.points.last().copied();
+            slices.push(Slice::new(frame.path_to_model(path), dependency));
+        }
+    }
+
+    if !gaps.is_empty() {
+        console_log!(
+            "Bridged {} gaps in the walls, left {} open",
+            gaps.iter().filter(|gap| gap.bridged).count(),
+            gaps.iter().filter(|gap| !gap.bridged).count()
+        );
+    }
+    let gaps = gaps
+        .into_iter()
+        .map(|gap| SliceGap {
+            start: frame.to_model(&gap.start).into(),
+            end: frame.to_model(&gap.end).into(),
+            layer: gap.i,
+            bridged: gap.bridged,
+        })
+        .collect();
+    if !unsupported.is_empty() {
+        console_log!("Left {} overhangs without tree support", unsupported.len());
+    }
+    let unsupported = unsupported
+        .iter()
+        .map(|point| frame.to_model(point).into())
+        .collect();
+
+    console_log!("Done");
+    Ok(SliceResult {
+        slices,
+        gcode,
+        gaps,
+        collisions: collisions.into_iter().map(SliceCollision::from).collect(),
+        travel_distance: travel,
+        travel_saved,
+        unsupported,
+    })
+    /*SliceResult {
+        slices: surfaces
+            .into_iter()
+            .flat_map(|(_, outlines, slices)| {
+                outlines
+                    .into_iter()
+                    .map(|slice| Slice::Ring {
+                        position: slice
+                            .points
+                            .into_iter()
+                            .flat_map(|point| [point.x as f32, point.y as f32, point.z as f32])
+                            .collect(),
+                    })
+                    .chain(slices.into_iter().map(|slice| {
+                        Slice::Ring {
+                            position: slice
+                                .path
+                                .into_iter()
+                                .flat_map(|point| [point.x as f32, point.y as f32, point.z as f32])
+                                .collect(),
+                        }
+                    }))
+            })
+            .chain(walls.flatten().map(|slice| {
+                Slice::Ring {
+                    position: slice
+                        .points
+                        .into_iter()
+                        .flat_map(|point| [point.x as f32, point.y as f32, point.z as f32])
+                        .collect(),
+                }
+            }))
+            .collect(),
+    }*/
+}
+
+fn slice_object(
     SliceOptions {
-        positions,
         layer_height,
         max_angle,
         min_surface_path_length,
         nozzle_diameter,
+        perimeters,
+        extruder,
+        layer_heights,
+        seam,
+        surfaces: surface_options,
+        support,
+        min_flow,
+        max_flow,
+        contours,
+        ..
     }: SliceOptions,
-) -> SliceResult {
-    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-
-    assert_eq!(positions.len() % 9, 0);
-
-    let mut surface_triangles = Vec::<Triangle>::with_capacity(positions.len() / 9);
-    let mut slicable_triangles = Vec::<Triangle>::with_capacity(positions.len() / 9);
-    for i in (0..positions.len()).step_by(9) {
-        let triangle = Triangle::new(
-            point![
-                positions[i] as FloatValue,
-                positions[i + 1] as FloatValue,
-                positions[i + 2] as FloatValue
-            ],
-            point![
-                positions[i + 3] as FloatValue,
-                positions[i + 4] as FloatValue,
-                positions[i + 5] as FloatValue
-            ],
-            point![
-                positions[i + 6] as FloatValue,
-                positions[i + 7] as FloatValue,
-                positions[i + 8] as FloatValue
-            ],
-        );
-
-        slicable_triangles.push(triangle);
-        let mut normal = triangle.normal.clone();
-        normal.z = normal.z.abs();
-        let angle = normal.angle(&BED_NORMAL);
-        if angle <= max_angle || relative_eq!(angle, max_angle) {
+    mesh: Mesh,
+    modifiers: Vec<Modifier>,
+    flow_scale: FloatValue,
+    first_surface: usize,
+    job: &Job,
+    progress: &mut Progress,
+) -> Result<ObjectPaths, Cancelled> {
+    let mut surface_triangles = Vec::<Triangle>::with_capacity(mesh.triangles.len());
+    let mut bottom_triangles = Vec::<Triangle>::new();
+    for triangle in mesh.triangles.iter().copied() {
+        // Overhangs resting on support are printed planar
+        if support.mode != SupportMode::None
+            && needs_support(&triangle, &BED_NORMAL, support.max_overhang_angle)
+        {
+            continue;
+        }
+        // Paint is more specific than the modifiers around it
+        let non_planar = match triangle.attributes.non_planar {
+            Paint::Enforcer => Some(true),
+            Paint::Blocker => Some(false),
+            Paint::None => non_planar_override(&modifiers, &triangle),
+        };
+        let top = match non_planar {
+            // Enforced surfaces still have to be reachable by the toolhead
+            Some(true) => is_top_surface(&triangle, &BED_NORMAL, max_reachable_angle(max_angle)),
+            Some(false) => false,
+            None => is_top_surface(&triangle, &BED_NORMAL, max_angle),
+        };
+        if top {
             surface_triangles.push(triangle);
+        } else if is_top_surface(&triangle, &-BED_NORMAL, max_angle) {
+            // Bottom surfaces can't be printed from above, they are bridged instead
+            bottom_triangles.push(triangle);
         }
     }
-    slicable_triangles.shrink_to_fit();
     surface_triangles.shrink_to_fit();
 
     console_log!("Creating Surfaces");
+    progress.stage(SliceStage::Surfaces);
     let min_surface_area = std::f64::consts::PI * (nozzle_diameter / 2.0).powi(2);
-    let mut surfaces = split_surface(surface_triangles)
-        .into_iter()
-        .filter(|mesh| {
-            let mut surface_area = 0.0;
-            for triangle in &mesh.triangles {
-                surface_area += triangle.area();
-                if surface_area >= min_surface_area {
-                    return true;
-                }
-            }
-            return false;
-        })
+    let surfaces = split_surface(surface_triangles, &surface_options.into(), job)?;
+    let mut surfaces = maybe_par_iter!(surfaces)
+        .filter(|mesh| has_min_area(mesh, min_surface_area))
         .map(|mesh| {
-            let outline = mesh
+            let mut outline = mesh
                 .outline_base_slice(Axis::Z)
                 .find_paths()
                 .into_iter()
                 .filter(|path| path.closed)
                 .collect::<Vec<_>>();
+            SeamPlacer::new(seam.into())
+                .with_paint(&mesh.triangles)
+                .place_layer(&mut outline);
             let surface = mesh
-                .slice_surface(Axis::X, nozzle_diameter)
+                .slice_surface(Axis::X, nozzle_diameter, job)
                 .filter(|path| {
                     let mut length = 0.0;
                     for pair in path.path.windows(2) {
@@ -99,102 +703,925 @@ pub fn slice(
             (mesh, outline, surface)
         })
         .collect::<Vec<_>>();
+    job.check()?;
     surfaces
         .sort_unstable_by(|(a, _, _), (b, _, _)| a.aabb.min.z.partial_cmp(&b.aabb.min.z).unwrap());
 
+    let wall_mesh = mesh;
+
+    console_log!("Computing Layer Heights");
+    progress.stage(SliceStage::LayerHeights);
+    let layers = match layer_heights {
+        LayerHeights::Uniform => wall_mesh.layer_positions(Axis::Z, layer_height),
+        LayerHeights::Adaptive {
+            min_layer_height,
+            max_layer_height,
+            max_cusp_height,
+        } => {
+            // Non-planar surfaces have no staircase, so only the walls
+            // printed in planar layers should limit the layer height
+            let surface_triangles = surfaces
+                .iter()
+                .flat_map(|(mesh, _, _)| mesh.triangles.iter().map(triangle_key))
+                .collect::<HashSet<_>>();
+            let planar_mesh = Mesh::from(
+                wall_mesh
+                    .triangles
+                    .iter()
+                    .filter(|triangle| !surface_triangles.contains(&triangle_key(triangle)))
+                    .copied()
+                    .collect::<Vec<_>>(),
+            );
+            adaptive_layer_positions(
+                &planar_mesh,
+                Axis::Z,
+                wall_mesh.aabb.min.z,
+                wall_mesh.aabb.max.z,
+                min_layer_height,
+                max_layer_height,
+                max_cusp_height,
+            )
+        }
+        LayerHeights::Profile { profile } => profile_layer_positions(
+            wall_mesh.aabb.min.z,
+            wall_mesh.aabb.max.z,
+            &profile,
+            layer_height,
+        ),
+    };
+
+    console_log!("Computing Surface Flow");
+    progress.stage(SliceStage::SurfaceFlow);
+    let flow_range = min_flow..=max_flow;
+    let bottom = wall_mesh.aabb.min.z;
+    let flows = maybe_par_iter!(&surfaces)
+        .enumerate()
+        .map(|(i, (_, outline, surface))| {
+            let below = surfaces
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, (mesh, _, _))| mesh)
+                .collect::<Vec<_>>();
+            let flow =
+                |path: &[_]| segment_flow(path, &layers, &below, bottom, layer_height, &flow_range);
+            (
+                outline
+                    .iter()
+                    .map(|ring| flow(&ring.points))
+                    .collect::<Vec<_>>(),
+                surface
+                    .iter()
+                    .map(|path| flow(&path.path))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let surfaces = surfaces
+        .into_iter()
+        .zip(flows)
+        .enumerate()
+        .map(
+            |(id, ((mesh, outline, surface), (outline_flow, surface_flow)))| {
+                // Surfaces are printed with the first layer that reaches into them
+                let layer = layers.partition_point(|z| *z < mesh.aabb.min.z);
+                // Surfaces never cross between extruders
+                let extruder = mesh.triangles[0].attributes.extruder.unwrap_or(extruder);
+                (
+                    mesh,
+                    outline
+                        .into_iter()
+                        .zip(outline_flow)
+                        .map(|(ring, flow)| {
+                            modify_flow(
+                                &modifiers,
+                                ToolPath {
+                                    role: PathRole::SurfacePerimeter,
+                                    points: ring.points,
+                                    flow,
+                                    width: nozzle_diameter,
+                                    layer,
+                                    closed: true,
+                                    surface: Some(first_surface + id),
+                                    extruder,
+                                },
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                    surface
+                        .into_iter()
+                        .zip(surface_flow)
+                        .map(|(path, flow)| {
+                            modify_flow(
+                                &modifiers,
+                                ToolPath {
+                                    role: PathRole::SurfaceFill,
+                                    points: path.path,
+                                    flow,
+                                    width: nozzle_diameter,
+                                    layer,
+                                    closed: false,
+                                    surface: Some(first_surface + id),
+                                    extruder,
+                                },
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            },
+        )
+        .collect::<Vec<_>>();
+
     console_log!("Creating Walls");
-    let wallMesh = Mesh::from(slicable_triangles);
-    let mut walls = wallMesh
-        .slice_paths(Axis::Z, layer_height)
-        .flat_map(|paths| paths.into_iter().filter(|path| path.closed))
-        .collect::<VecDeque<_>>();
+    progress.stage(SliceStage::Walls);
+    let mut seam_placer = SeamPlacer::new(seam.into()).with_paint(&wall_mesh.triangles);
+    let painted = wall_mesh
+        .triangles
+        .iter()
+        .filter(|triangle| triangle.attributes.extruder.is_some())
+        .copied()
+        .collect::<Vec<_>>();
+    let painted = (!painted.is_empty()).then(|| Mesh::from(painted));
+    let mut gaps = Vec::new();
+    let mut walls = wall_mesh
+        .slice_contours(Axis::Z, layers.clone(), contours.into(), job)
+        .flat_map(|(paths, layer_gaps)| {
+            gaps.extend(layer_gaps);
+            let mut rings = paths
+                .into_iter()
+                .filter(|path| path.closed)
+                .collect::<Vec<_>>();
+            seam_placer.place_layer(&mut rings);
+            // Inner walls are printed first, from the inside out
+            inner_walls(
+                &rings,
+                |ring| ring_perimeters(&modifiers, ring, perimeters, nozzle_diameter),
+                nozzle_diameter,
+            )
+            .into_iter()
+            .zip(rings)
+            .flat_map(|(inner, ring)| {
+                let inner = inner.into_iter().rev().collect::<Vec<_>>();
+                split_walls_by_extruder(inner, &ring, painted.as_ref(), extruder)
+                    .into_iter()
+                    .map(|(extruder, run)| (PathRole::InnerWall, extruder, run))
+                    .chain(
+                        split_by_extruder(ring, painted.as_ref(), extruder)
+                            .into_iter()
+                            .map(|(extruder, run)| (PathRole::OuterWall, extruder, run)),
+                    )
+            })
+            .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    job.check()?;
+
+    let support_settings = SupportSettings {
+        max_overhang_angle: support.max_overhang_angle,
+        spacing: support.spacing,
+        gap: support.gap,
+        branch_angle: support.branch_angle,
+        branch_diameter: support.branch_diameter,
+    };
+    let (support_paths, unsupported) = match support.mode {
+        SupportMode::None => (vec![], vec![]),
+        SupportMode::Lines | SupportMode::Grid => {
+            console_log!("Creating Support");
+            progress.stage(SliceStage::Support);
+            let pattern = if support.mode == SupportMode::Grid {
+                SupportPattern::Grid
+            } else {
+                SupportPattern::Lines
+            };
+            let paths =
+                generate_support(&wall_mesh, &layers, pattern, &support_settings, &BED_NORMAL);
+            (paths, vec![])
+        }
+        SupportMode::Tree => {
+            console_log!("Creating Tree Support");
+            progress.stage(SliceStage::Support);
+            generate_tree_support(
+                &wall_mesh,
+                &layers,
+                &surfaces.iter().map(|(mesh, _, _)| mesh).collect::<Vec<_>>(),
+                max_angle,
+                &support_settings,
+                &BED_NORMAL,
+            )
+        }
+    };
+    let bridges = generate_bridges(
+        &split_surface(bottom_triangles, &SplitSettings::default(), job)?,
+        &layers,
+        nozzle_diameter,
+    );
+    if !support_paths.is_empty() || !bridges.is_empty() {
+        walls.extend(
+            support_paths
+                .into_iter()
+                .map(|path| (PathRole::Support, extruder, path))
+                .chain(
+                    bridges
+                        .into_iter()
+                        .map(|path| (PathRole::Bridge, extruder, path)),
+                ),
+        );
+        walls.sort_by_key(|(_, _, path)| path.i);
+    }
+
+    job.check()?;
+
+    Ok(ObjectPaths {
+        surfaces,
+        walls,
+        gaps,
+        unsupported,
+        layers,
+        modifiers,
+        bottom: wall_mesh.aabb.min.z,
+        layer_height,
+        nozzle_diameter,
+        max_angle,
+        extruder,
+        flow_scale,
+    })
+}
+
+/// Resolves the order objects printed together are printed in and adds their levels.
+///
+/// The objects share one skirt, brim and raft, and every surface is traced against the
+/// walls of all of them, so no object is in the way of the nozzle while it prints a surface.
+/// Returns the gaps in the walls and the overhangs left without support,
+/// raised along with the objects above the raft.
+fn resolve_dependencies(
+    mut objects: Vec<ObjectPaths>,
+    settings: &PlateSettings,
+    levels: &mut Vec<Vec<ToolPath>>,
+    job: &Job,
+    progress: &mut Progress,
+) -> Result<(Vec<Gap>, Vec<Point3<FloatValue>>), Cancelled> {
+    let mut surfaces = Vec::new();
+    let mut walls = Vec::new();
+    let mut gaps = Vec::new();
+    let mut unsupported = Vec::new();
+    for (k, object) in objects.iter_mut().enumerate() {
+        surfaces.extend(
+            std::mem::take(&mut object.surfaces)
+                .into_iter()
+                .map(|(mesh, perimeters, fill)| (k, mesh, perimeters, fill)),
+        );
+        walls.extend(
+            std::mem::take(&mut object.walls)
+                .into_iter()
+                .map(|(role, extruder, wall)| (k, role, extruder, wall)),
+        );
+        gaps.append(&mut object.gaps);
+        unsupported.append(&mut object.unsupported);
+    }
+    // Walls of different objects at the same height end up in the same level
+    surfaces.sort_by(|a, b| a.1.aabb.min.z.total_cmp(&b.1.aabb.min.z));
+    walls.sort_by(|a, b| a.3.d.total_cmp(&b.3.d));
+
+    console_log!("Creating Adhesion");
+    progress.stage(SliceStage::Adhesion);
+    let first_layer = walls.first().map(|(_, _, _, wall)| wall.d);
+    // Every object starts on the bed, but not necessarily with the same layer height
+    let first_layer_paths = objects
+        .iter()
+        .enumerate()
+        .flat_map(|(k, object)| {
+            let first = walls
+                .iter()
+                .find(|(j, _, _, _)| *j == k)
+                .map(|(_, _, _, wall)| wall.i);
+            walls
+                .iter()
+                .filter(move |(j, _, _, wall)| *j == k && Some(wall.i) == first)
+                .map(move |(_, role, _, wall)| (*role, object, wall))
+        })
+        .collect::<Vec<_>>();
+    let hull = first_layer_hull(
+        &first_layer_paths
+            .iter()
+            .map(|(_, _, wall)| *wall)
+            .collect::<Vec<_>>(),
+    );
+    let bottom = objects
+        .iter()
+        .map(|object| object.bottom)
+        .fold(FloatValue::INFINITY, FloatValue::min);
+    let raft_settings = settings.raft;
+    let raft_height = raft_settings.height(settings.layer_height);
+    // Everything above the raft is raised by its height
+    let raise = |mut path: ToolPath| {
+        if raft_height > 0.0 {
+            path.layer += raft_settings.layers();
+            for point in path.points.iter_mut() {
+                point.z += raft_height;
+            }
+        }
+        path
+    };
+    // Adhesion is printed with the extruder of the first object
+    let extruder = objects.first().map_or(0, |object| object.extruder);
+    let adhesion = |paths: Vec<ToolPath>| {
+        paths
+            .into_iter()
+            .map(|path| ToolPath { extruder, ..path })
+            .collect::<Vec<_>>()
+    };
+    // Paths within a level do not depend on each other
+    if raft_settings.layers() > 0 {
+        let skirt = skirt(
+            &offset(&hull, raft_settings.margin),
+            bottom + settings.layer_height,
+            settings.skirt.loops,
+            settings.skirt.distance,
+            settings.nozzle_diameter,
+        );
+        resolve_level(levels, adhesion(skirt), &settings.simplify, progress);
+        for level in raft(
+            &hull,
+            bottom,
+            settings.layer_height,
+            settings.nozzle_diameter,
+            &raft_settings,
+        ) {
+            resolve_level(levels, adhesion(level), &settings.simplify, progress);
+        }
+    } else if let Some(d) = first_layer {
+        let skirt = skirt(
+            &hull,
+            d,
+            settings.skirt.loops,
+            settings.skirt.distance,
+            settings.nozzle_diameter,
+        );
+        resolve_level(levels, adhesion(skirt), &settings.simplify, progress);
+    }
+    // The raft already holds the first layer down, a brim on top of it has nothing to grip
+    let brim_loops = if raft_settings.layers() > 0 {
+        0
+    } else {
+        settings.brim.loops
+    };
+    let brim = brim(
+        &first_layer_paths
+            .iter()
+            .filter(|(role, _, _)| *role == PathRole::OuterWall)
+            .map(|(_, _, wall)| *wall)
+            .collect::<Vec<_>>(),
+        brim_loops,
+        settings.nozzle_diameter,
+        settings.brim.outside_only,
+    );
+    resolve_level(
+        levels,
+        adhesion(brim).into_iter().map(raise).collect(),
+        &settings.simplify,
+        progress,
+    );
+
+    let flow_scale = |mut path: ToolPath, object: &ObjectPaths| {
+        path.flow
+            .iter_mut()
+            .for_each(|flow| *flow *= object.flow_scale);
+        path
+    };
+    let mut walls = VecDeque::from(walls);
+    let total_walls = walls.len().max(1);
     let mut active_surfaces = Vec::new();
-    let mut out = Vec::new();
+    let mut wall_level = Vec::new();
+    let mut wall_layer = None;
 
     console_log!("Resolving dependencies");
-    while let Some(mut wall) = walls.pop_front() {
+    progress.stage(SliceStage::Dependencies);
+    loop {
+        job.check()?;
+        let next = walls.pop_front();
+        // Once the walls run out, every remaining surface is printed
+        // followed by the walls it held back
         active_surfaces.extend(
             surfaces
-                .extract_if(|surface| surface.0.aabb.min.z <= wall.aabb.max.z)
+                .extract_if(.., |surface| {
+                    next.as_ref()
+                        .is_none_or(|(_, _, _, wall)| surface.1.aabb.min.z <= wall.aabb.max.z)
+                })
                 .map(|surface| (surface, Vec::new())),
         );
 
-        let deactivate =
-            active_surfaces.extract_if(|element| element.0 .0.aabb.max.z < wall.aabb.min.z);
-        for (surface, surface_walls) in deactivate {
-            for ring in surface.1 {
-                out.push(ring.points);
-            }
-            for path in surface.2 {
-                out.push(path.path);
-            }
+        let deactivate = active_surfaces.extract_if(.., |element| {
+            next.as_ref()
+                .is_none_or(|(_, _, _, wall)| element.0 .1.aabb.max.z < wall.aabb.min.z)
+        });
+        for ((k, _, perimeters, fill), surface_walls) in deactivate {
+            resolve_level(
+                levels,
+                std::mem::take(&mut wall_level),
+                &settings.simplify,
+                progress,
+            );
+            let level = perimeters
+                .into_iter()
+                .chain(fill)
+                .map(|path| raise(flow_scale(path, &objects[k])))
+                .collect();
+            resolve_level(levels, level, &settings.simplify, progress);
+            wall_layer = None;
             for wall in surface_walls {
                 walls.push_front(wall);
             }
         }
 
-        for surface in active_surfaces.iter_mut() {
-            let held = wall
-                .points
-                .extract_if(|point| !trace_surface(&point, &surface.0 .0, max_angle))
+        let Some((k, role, wall_extruder, mut wall)) = next else {
+            if walls.is_empty() {
+                break;
+            }
+            continue;
+        };
+
+        // Surfaces are traced against the walls of every object, the nozzle printing
+        // them must not run into a neighbour that was printed first
+        for ((j, surface, _, _), surface_walls) in active_surfaces.iter_mut() {
+            let max_angle = objects[*j].max_angle;
+            let in_keep_out = maybe_par_iter!(&wall.points)
+                .map(|point| !trace_surface(point, surface, max_angle))
                 .collect::<Vec<_>>();
+            let mut held = Vec::new();
+            let mut kept = Vec::new();
+            for (point, in_keep_out) in std::mem::take(&mut wall.points)
+                .into_iter()
+                .zip(in_keep_out)
+            {
+                if in_keep_out {
+                    held.push(point);
+                } else {
+                    kept.push(point);
+                }
+            }
+            wall.points = kept;
             if !held.is_empty() {
-                surface.1.push(SlicePath {
-                    points: held,
-                    ..wall
-                });
+                surface_walls.push((
+                    k,
+                    role,
+                    wall_extruder,
+                    SlicePath {
+                        points: held,
+                        ..wall
+                    },
+                ));
             }
         }
 
         if !wall.points.is_empty() {
-            out.push(wall.points);
+            let object = &objects[k];
+            let thickness = if wall.i == 0 {
+                object.layer_height
+            } else {
+                object.layers[wall.i] - object.layers[wall.i - 1]
+            };
+            let closed = wall.closed
+                && relative_eq!(wall.points.first().unwrap(), wall.points.last().unwrap());
+            if wall_layer != Some(wall.d) {
+                resolve_level(
+                    levels,
+                    std::mem::take(&mut wall_level),
+                    &settings.simplify,
+                    progress,
+                );
+                wall_layer = Some(wall.d);
+                let remaining = walls.len() as FloatValue / total_walls as FloatValue;
+                progress.layer(100.0 * (1.0 - remaining), wall.i);
+            }
+            let path = modify_flow(
+                &object.modifiers,
+                ToolPath {
+                    role,
+                    width: object.nozzle_diameter,
+                    layer: wall.i,
+                    extruder: wall_extruder,
+                    ..ToolPath::planar(wall.points, thickness / object.layer_height, closed)
+                },
+            );
+            wall_level.push(raise(flow_scale(path, object)));
         }
     }
+    resolve_level(levels, wall_level, &settings.simplify, progress);
 
-    console_log!("Done");
-    SliceResult {
-        slices: out
-            .into_iter()
-            .map(|slice| Slice::Ring {
-                position: slice
-                    .into_iter()
-                    .flat_map(|point| [point.x as f32, point.y as f32, point.z as f32])
-                    .collect(),
-            })
-            .collect(),
+    let gaps = gaps
+        .into_iter()
+        .map(|gap| Gap {
+            i: gap.i + raft_settings.layers(),
+            start: gap.start + vector![0.0, 0.0, raft_height],
+            end: gap.end + vector![0.0, 0.0, raft_height],
+            ..gap
+        })
+        .collect();
+    let unsupported = unsupported
+        .into_iter()
+        .map(|point| point + vector![0.0, 0.0, raft_height])
+        .collect();
+    Ok((gaps, unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::vector;
+
+    use crate::{
+        orientation_with_job, place_mesh,
+        progress::Progress,
+        result::{
+            LayerHeights, OrientationOptions, PlacementOptions, SliceError, SliceOptions,
+            SliceProgress, SliceResult, SliceRole, SupportMode, WipeTowerOptions,
+        },
+        slice_with_progress,
+        slicer::{job::Job, mesh::Mesh},
+        triangles_from_positions,
+    };
+
+    /// A closed 10 × 10 box whose top rises from `low` at y = 0 to `high` at y = 10
+    fn wedge(low: f32, high: f32) -> Vec<f32> {
+        let bottom = [
+            [0.0, 0.0, 0.0],
+            [10.0, 0.0, 0.0],
+            [10.0, 10.0, 0.0],
+            [0.0, 10.0, 0.0],
+        ];
+        let top = [
+            [0.0, 0.0, low],
+            [10.0, 0.0, low],
+            [10.0, 10.0, high],
+            [0.0, 10.0, high],
+        ];
+        let quads = [
+            [bottom[0], bottom[3], bottom[2], bottom[1]],
+            [top[0], top[1], top[2], top[3]],
+            [bottom[0], bottom[1], top[1], top[0]],
+            [bottom[1], bottom[2], top[2], top[1]],
+            [bottom[2], bottom[3], top[3], top[2]],
+            [bottom[3], bottom[0], top[0], top[3]],
+        ];
+        quads
+            .iter()
+            .flat_map(|[a, b, c, d]| [a, b, c, a, c, d])
+            .flatten()
+            .copied()
+            .collect()
     }
-    /*SliceResult {
-        slices: surfaces
-            .into_iter()
-            .flat_map(|(_, outlines, slices)| {
-                outlines
-                    .into_iter()
-                    .map(|slice| Slice::Ring {
-                        position: slice
-                            .points
-                            .into_iter()
-                            .flat_map(|point| [point.x as f32, point.y as f32, point.z as f32])
-                            .collect(),
-                    })
-                    .chain(slices.into_iter().map(|slice| {
-                        Slice::Ring {
-                            position: slice
-                                .path
-                                .into_iter()
-                                .flat_map(|point| [point.x as f32, point.y as f32, point.z as f32])
-                                .collect(),
-                        }
-                    }))
+
+    fn options(positions: Vec<f32>) -> SliceOptions {
+        let mut options: SliceOptions = serde_json::from_str(
+            r#"{"layerHeight": 0.2, "nozzleDiameter": 0.4, "maxAngle": 0.5, "minSurfacePathLength": 0.8}"#,
+        )
+        .unwrap();
+        options.positions = positions;
+        options
+    }
+
+    fn slice(options: SliceOptions) -> SliceResult {
+        slice_with_progress(options, &Job::new(), &mut Progress::none()).unwrap()
+    }
+
+    #[test]
+    fn test_surfaces_after_walls() {
+        // Nothing is above the sloped top, so it is still pending when the walls run out
+        let result = slice(options(wedge(5.0, 6.0)));
+        let fill = result
+            .slices
+            .iter()
+            .filter(|slice| slice.role == SliceRole::SurfaceFill)
+            .collect::<Vec<_>>();
+        assert!(!fill.is_empty());
+
+        // It is printed once the walls below it are done
+        let surface = fill.iter().map(|slice| slice.dependency).min().unwrap();
+        assert!(result
+            .slices
+            .iter()
+            .filter(|slice| slice.role == SliceRole::OuterWall)
+            .all(|wall| wall.dependency < surface));
+    }
+
+    /// A plate of a low wedge with a taller box right next to its sloped top
+    fn plate(mode: serde_json::Value) -> SliceOptions {
+        let mut options: SliceOptions = serde_json::from_value(serde_json::json!({
+            "layerHeight": 0.2,
+            "nozzleDiameter": 0.4,
+            "maxAngle": 0.5,
+            "minSurfacePathLength": 0.8,
+            "skirt": {"loops": 1},
+            "plate": mode,
+            "objects": [
+                {"positions": [], "placement": {"center": [20.0, 50.0]}},
+                {"positions": [], "placement": {"center": [31.0, 50.0]}},
+            ],
+        }))
+        .unwrap();
+        options.objects[0].positions = wedge(2.0, 3.0);
+        options.objects[1].positions = wedge(8.0, 8.0);
+        options
+    }
+
+    #[test]
+    fn test_plate() {
+        let result = slice(plate(serde_json::json!({"type": "allAtOnce"})));
+        assert!(result.collisions.is_empty());
+        // The objects share one skirt around both of them
+        let skirts = result
+            .slices
+            .iter()
+            .filter(|slice| slice.role == SliceRole::Skirt)
+            .collect::<Vec<_>>();
+        assert_eq!(skirts.len(), 1);
+        assert!(skirts[0].position.chunks(3).any(|point| point[0] < 15.0));
+        assert!(skirts[0].position.chunks(3).any(|point| point[0] > 36.0));
+
+        let walls = result
+            .slices
+            .iter()
+            .filter(|slice| slice.role == SliceRole::OuterWall)
+            .collect::<Vec<_>>();
+        let first = |left: bool| {
+            walls
+                .iter()
+                .filter(|wall| wall.layer == 0 && (wall.position[0] < 25.5) == left)
+                .map(|wall| wall.dependency)
+                .min()
+                .unwrap()
+        };
+        // The first layers of both objects are printed together
+        assert_eq!(first(true), first(false));
+
+        // Walls of the box above the top of the wedge wait for it, or the nozzle
+        // printing the top would run into them
+        let top = result
+            .slices
+            .iter()
+            .filter(|slice| slice.role == SliceRole::SurfaceFill)
+            .map(|slice| slice.dependency)
+            .min()
+            .unwrap();
+        assert!(walls
+            .iter()
+            .filter(|wall| wall.position.chunks(3).all(|point| point[2] > 4.0))
+            .any(|wall| wall.dependency > top));
+        assert!(walls
+            .iter()
+            .filter(|wall| wall.position.chunks(3).all(|point| point[2] < 2.0))
+            .all(|wall| wall.dependency < top));
+
+        // Objects printed one by one each get their own skirt
+        let result = slice(plate(serde_json::json!({
+            "type": "sequential",
+            "toolhead": [-20.0, -10.0, 10.0, 10.0],
+            "gantryHeight": 20.0,
+        })));
+        let skirts = result
+            .slices
+            .iter()
+            .filter(|slice| slice.role == SliceRole::Skirt)
+            .count();
+        assert_eq!(skirts, 2);
+    }
+
+    #[test]
+    fn test_non_planar_override() {
+        let fill = |positions: Vec<f32>, non_planar: Option<bool>| {
+            let mut options = options(positions);
+            if let Some(non_planar) = non_planar {
+                options.modifiers = serde_json::from_value(serde_json::json!([{
+                    "volume": {"type": "box", "center": [5.0, 5.0, 20.0], "size": [20.0, 20.0, 60.0]},
+                    "nonPlanar": non_planar,
+                }]))
+                .unwrap();
+            }
+            slice(options)
+                .slices
+                .iter()
+                .filter(|slice| slice.role == SliceRole::SurfaceFill)
+                .count()
+        };
+        // Slightly steeper than the maximum angle, the top is only non-planar when enforced
+        assert_eq!(fill(wedge(2.0, 8.0), None), 0);
+        assert!(fill(wedge(2.0, 8.0), Some(true)) > 0);
+        // The toolhead can't reach a top this steep, even when it is enforced
+        assert_eq!(fill(wedge(1.0, 31.0), Some(true)), 0);
+        // Blocked tops are printed planar
+        assert!(fill(wedge(5.0, 6.0), None) > 0);
+        assert_eq!(fill(wedge(5.0, 6.0), Some(false)), 0);
+    }
+
+    #[test]
+    fn test_build_direction() {
+        let mesh = || Mesh::from(triangles_from_positions(&wedge(5.0, 6.0)));
+        let sideways = vector![-1.0, 0.0, 0.0];
+
+        // Turned onto its side, the model hangs below the bed unless it is dropped onto it
+        let (placed, _) = place_mesh(mesh(), &sideways, &PlacementOptions::default()).unwrap();
+        assert_relative_eq!(placed.aabb.min.z, 0.0, epsilon = 1e-9);
+        let kept = PlacementOptions {
+            drop_to_bed: Some(false),
+            ..Default::default()
+        };
+        let (placed, _) = place_mesh(mesh(), &sideways, &kept).unwrap();
+        assert_relative_eq!(placed.aabb.min.z, -10.0, epsilon = 1e-9);
+
+        let mut options = options(wedge(5.0, 6.0));
+        options.build_direction = [0.0, 0.0, 0.0];
+        let result = slice_with_progress(options, &Job::new(), &mut Progress::none());
+        assert!(matches!(result.err(), Some(SliceError::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let invalid = |change: fn(&mut SliceOptions)| {
+            let mut options = options(wedge(5.0, 6.0));
+            change(&mut options);
+            matches!(
+                slice_with_progress(options, &Job::new(), &mut Progress::none()),
+                Err(SliceError::InvalidOptions(_))
+            )
+        };
+        assert!(!invalid(|_| {}));
+        assert!(invalid(|options| options.nozzle_diameter = 0.0));
+        assert!(invalid(|options| options.nozzle_diameter = f64::NAN));
+        assert!(!invalid(|options| options.support.spacing = 0.0));
+        assert!(invalid(|options| {
+            options.support.mode = SupportMode::Lines;
+            options.support.spacing = 0.0
+        }));
+        assert!(invalid(|options| {
+            options.support.mode = SupportMode::Tree;
+            options.support.branch_diameter = f64::NAN
+        }));
+        assert!(!invalid(|options| options.raft.base_spacing = 0.0));
+        assert!(invalid(|options| {
+            options.wipe_tower = Some(WipeTowerOptions {
+                position: [50.0, 50.0],
+                width: 10.0,
+                depth: 1.0,
+                purge_volume: 10.0,
             })
-            .chain(walls.flatten().map(|slice| {
-                Slice::Ring {
-                    position: slice
-                        .points
-                        .into_iter()
-                        .flat_map(|point| [point.x as f32, point.y as f32, point.z as f32])
-                        .collect(),
+        }));
+        assert!(invalid(|options| {
+            options.raft.base_layers = 1;
+            options.raft.base_spacing = 0.0
+        }));
+        assert!(invalid(|options| {
+            options.raft.interface_layers = 2;
+            options.raft.interface_spacing = -0.5
+        }));
+        assert!(!invalid(|options| {
+            options.layer_heights = LayerHeights::Profile {
+                profile: vec![[0.0, 0.1], [2.0, 0.3]],
+            }
+        }));
+        assert!(invalid(|options| {
+            options.layer_heights = LayerHeights::Profile {
+                profile: vec![[0.0, 0.1], [2.0, 0.0]],
+            }
+        }));
+        assert!(invalid(|options| {
+            options.layer_heights = LayerHeights::Profile {
+                profile: vec![[2.0, 0.1], [2.0, 0.3]],
+            }
+        }));
+    }
+
+    #[test]
+    fn test_invalid_orientation_options() {
+        let orientations = |change: serde_json::Value| {
+            let mut options =
+                serde_json::json!({"positions": [], "nozzleDiameter": 0.4, "maxAngle": 0.5});
+            options
+                .as_object_mut()
+                .unwrap()
+                .extend(change.as_object().unwrap().clone());
+            let mut options: OrientationOptions = serde_json::from_value(options).unwrap();
+            options.positions = wedge(5.0, 6.0);
+            orientation_with_job(options, &Job::new()).map(|result| result.orientations.len())
+        };
+        assert_eq!(orientations(serde_json::json!({"results": 2})), Ok(2));
+        for invalid in [
+            serde_json::json!({"samples": 0}),
+            serde_json::json!({"results": 0}),
+            serde_json::json!({"nozzleDiameter": 0.0}),
+            serde_json::json!({"overhangWeight": -1.0}),
+        ] {
+            assert!(matches!(
+                orientations(invalid),
+                Err(SliceError::InvalidOptions(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_invalid_placement() {
+        let place = |placement: PlacementOptions| {
+            let mesh = Mesh::from(triangles_from_positions(&wedge(5.0, 6.0)));
+            place_mesh(mesh, &vector![0.0, 0.0, 1.0], &placement).err()
+        };
+        let mut flatten = [0.0; 16];
+        flatten[0] = 1.0;
+        flatten[5] = 1.0;
+        flatten[15] = 1.0;
+        assert!(matches!(
+            place(PlacementOptions {
+                transform: Some(flatten),
+                ..Default::default()
+            }),
+            Some(SliceError::InvalidOptions(_))
+        ));
+        assert!(matches!(
+            place(PlacementOptions {
+                lay_flat_face: Some(12),
+                ..Default::default()
+            }),
+            Some(SliceError::InvalidOptions(_))
+        ));
+        assert!(place(PlacementOptions {
+            lay_flat_face: Some(11),
+            ..Default::default()
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let job = Job::new();
+        let handle = job.clone();
+        let mut levels = 0;
+        let mut progress = Progress::new(|event| {
+            if let SliceProgress::Level { .. } = event {
+                levels += 1;
+                if levels == 3 {
+                    handle.cancel();
                 }
-            }))
-            .collect(),
-    }*/
+            }
+        });
+        let result = slice_with_progress(options(wedge(5.0, 6.0)), &job, &mut progress);
+        drop(progress);
+        assert_eq!(result.err(), Some(SliceError::Cancelled));
+        // No further levels are resolved once the job is cancelled
+        assert_eq!(levels, 3);
+
+        let cancelled = Job::new();
+        cancelled.cancel();
+        let result =
+            slice_with_progress(options(wedge(5.0, 6.0)), &cancelled, &mut Progress::none());
+        assert_eq!(result.err(), Some(SliceError::Cancelled));
+    }
+
+    #[test]
+    fn test_streamed_levels_simplified() {
+        let mut options = options(wedge(5.0, 6.0));
+        options.simplify.max_deviation = 0.5;
+        options.simplify.min_segment_length = 1.0;
+        let mut streamed = vec![];
+        let mut progress = Progress::new(|event| {
+            if let SliceProgress::Level { dependency, slices } = event {
+                let points = slices
+                    .iter()
+                    .map(|slice| slice.position.len())
+                    .sum::<usize>();
+                streamed.push((dependency, points));
+            }
+        });
+        let result = slice_with_progress(options, &Job::new(), &mut progress).unwrap();
+        drop(progress);
+
+        // Levels are reported with the paths they end up with
+        let mut printed = vec![0; streamed.len()];
+        for slice in &result.slices {
+            if slice.role != SliceRole::Travel {
+                printed[slice.dependency] += slice.position.len();
+            }
+        }
+        assert_eq!(
+            streamed,
+            printed.into_iter().enumerate().collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel() {
+        let serial = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+            .install(|| slice(options(wedge(5.0, 6.0))));
+        let parallel = slice(options(wedge(5.0, 6.0)));
+        let positions = |result: &SliceResult| {
+            result
+                .slices
+                .iter()
+                .map(|slice| slice.position.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(positions(&serial), positions(&parallel));
+        assert_eq!(serial.gcode, parallel.gcode);
+    }
 }
@@ -0,0 +1,74 @@
+use crate::{
+    result::{Slice, SliceProgress, SliceStage},
+    slicer::{frame::BuildFrame, toolpath::ToolPath},
+};
+
+/// Reports the progress of a slicing job while it runs
+pub struct Progress<'a> {
+    callback: Option<Box<dyn FnMut(SliceProgress) + 'a>>,
+    stage: SliceStage,
+    frame: BuildFrame,
+}
+
+impl<'a> Progress<'a> {
+    /// A reporter that discards all events
+    pub fn none() -> Self {
+        Self {
+            callback: None,
+            stage: SliceStage::Surfaces,
+            frame: BuildFrame::default(),
+        }
+    }
+
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: FnMut(SliceProgress) + 'a,
+    {
+        Self {
+            callback: Some(Box::new(callback)),
+            stage: SliceStage::Surfaces,
+            frame: BuildFrame::default(),
+        }
+    }
+
+    /// Reported paths are transformed back from this frame into the frame of the model
+    pub fn set_frame(&mut self, frame: BuildFrame) {
+        self.frame = frame;
+    }
+
+    pub fn stage(&mut self, stage: SliceStage) {
+        self.stage = stage;
+        self.emit(None, 0);
+    }
+
+    /// Progress within the current stage
+    pub fn layer(&mut self, percent: f64, layer: usize) {
+        self.emit(Some(percent.clamp(0.0, 100.0)), layer);
+    }
+
+    /// Reports a resolved dependency level.
+    /// Its paths may still be reordered before they end up in the result.
+    pub fn level(&mut self, dependency: usize, paths: &[ToolPath]) {
+        let frame = self.frame;
+        if let Some(callback) = self.callback.as_mut() {
+            callback(SliceProgress::Level {
+                dependency,
+                slices: paths
+                    .iter()
+                    .map(|path| Slice::new(frame.path_to_model(path.clone()), dependency))
+                    .collect(),
+            });
+        }
+    }
+
+    fn emit(&mut self, percent: Option<f64>, layer: usize) {
+        let stage = self.stage;
+        if let Some(callback) = self.callback.as_mut() {
+            callback(SliceProgress::Progress {
+                stage,
+                percent,
+                layer,
+            });
+        }
+    }
+}
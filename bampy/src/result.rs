@@ -1,39 +1,976 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 use tsify::Tsify;
 
-#[derive(Tsify, Serialize, Deserialize)]
+use crate::slicer::{
+    arc::ArcSettings,
+    base_slices::{ContourSettings, MAX_GAP, SNAP_TOLERANCE},
+    job::Cancelled,
+    modifier::ModifierSettings,
+    orientation::OrientationScore,
+    plate::Collision,
+    seam::Seam,
+    simplify::SimplifySettings,
+    split_surface::{Connectivity, SplitSettings},
+    toolpath::{PathRole, ToolPath},
+};
+
+#[derive(Tsify, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 #[tsify(from_wasm_abi)]
 pub struct SliceOptions {
+    /// The model, ignored if the plate has objects
     #[tsify(type = "Float32Array")]
+    #[serde(default)]
     pub positions: Vec<f32>,
+    /// Several objects sliced on one plate, each with its own placement and settings
+    #[serde(default)]
+    pub objects: Vec<PlateObject>,
+    #[serde(default)]
+    pub plate: PlateMode,
     pub layer_height: f64,
     pub nozzle_diameter: f64,
     pub max_angle: f64,
     pub min_surface_path_length: f64,
+    /// Direction the model is built towards, in the frame of the positions.
+    /// The model is sliced with this direction pointing up from the bed.
+    #[serde(default = "default_build_direction")]
+    pub build_direction: [f64; 3],
+    #[serde(default)]
+    pub placement: PlacementOptions,
+    #[serde(default)]
+    pub paint: PaintOptions,
+    /// Volumes overriding settings in parts of the model, placed along with it
+    #[serde(default)]
+    pub modifiers: Vec<ModifierOptions>,
+    /// Number of walls around planar layers
+    #[serde(default = "default_perimeters")]
+    pub perimeters: usize,
+    /// Index of the extruder the model is printed with where it isn't painted,
+    /// counting from 0 like the tools of the G-code
+    #[serde(default)]
+    pub extruder: usize,
+    /// Purges the nozzle after tool changes, which are emitted without purging otherwise
+    #[serde(default)]
+    pub wipe_tower: Option<WipeTowerOptions>,
+    #[serde(default)]
+    pub layer_heights: LayerHeights,
+    #[serde(default)]
+    pub seam: SeamPlacement,
+    #[serde(default)]
+    pub surfaces: SurfaceOptions,
+    #[serde(default)]
+    pub support: SupportOptions,
+    #[serde(default)]
+    pub skirt: SkirtOptions,
+    #[serde(default)]
+    pub brim: BrimOptions,
+    #[serde(default)]
+    pub raft: RaftOptions,
+    /// Reorder paths within each dependency level to reduce travel
+    #[serde(default = "default_optimise_path_order")]
+    pub optimise_path_order: bool,
+    /// Lower bound of the extrusion multiplier on non-planar surfaces
+    #[serde(default = "default_min_flow")]
+    pub min_flow: f64,
+    /// Upper bound of the extrusion multiplier on non-planar surfaces
+    #[serde(default = "default_max_flow")]
+    pub max_flow: f64,
+    #[serde(default = "default_filament_diameter")]
+    pub filament_diameter: f64,
+    /// Print speed in mm/s
+    #[serde(default = "default_print_speed")]
+    pub print_speed: f64,
+    /// Travel speed in mm/s
+    #[serde(default = "default_travel_speed")]
+    pub travel_speed: f64,
+    /// Clearance above the higher end of a travel move
+    #[serde(default = "default_z_hop")]
+    pub z_hop: f64,
+    /// Filament retracted before each tool change and primed again after it, in mm
+    #[serde(default = "default_tool_change_retract")]
+    pub tool_change_retract: f64,
+    /// Emits G2 and G3 moves for paths running along a circle
+    #[serde(default)]
+    pub arc_fitting: Option<ArcFittingOptions>,
+    #[serde(default)]
+    pub simplify: SimplifyOptions,
+    #[serde(default)]
+    pub contours: ContourOptions,
 }
 
-#[derive(Tsify, Serialize, Deserialize)]
+impl SliceOptions {
+    /// Rejects settings that can't be sliced, before any work is done
+    pub fn validate(&self) -> Result<(), SliceError> {
+        positive("layer height", self.layer_height)?;
+        if let LayerHeights::Adaptive {
+            min_layer_height,
+            max_layer_height,
+            max_cusp_height,
+        } = self.layer_heights
+        {
+            if min_layer_height.is_nan()
+                || max_layer_height.is_nan()
+                || min_layer_height <= 0.0
+                || min_layer_height > max_layer_height
+            {
+                return Err(SliceError::InvalidOptions(format!(
+                    "adaptive layer heights need 0 < {} <= {}",
+                    min_layer_height, max_layer_height
+                )));
+            }
+            positive("maximum cusp height", max_cusp_height)?;
+        }
+        if let LayerHeights::Profile { profile } = &self.layer_heights {
+            for [z, height] in profile {
+                if !z.is_finite() || height.is_nan() || *height <= 0.0 {
+                    return Err(SliceError::InvalidOptions(format!(
+                        "the layer height {} at {} of the profile is not positive",
+                        height, z
+                    )));
+                }
+            }
+            if let Some(pair) = profile.windows(2).find(|pair| pair[0][0] >= pair[1][0]) {
+                return Err(SliceError::InvalidOptions(format!(
+                    "the heights of the layer height profile must ascend, {} is followed by {}",
+                    pair[0][0], pair[1][0]
+                )));
+            }
+        }
+        if self.min_flow.is_nan() || self.max_flow.is_nan() || self.min_flow > self.max_flow {
+            return Err(SliceError::InvalidOptions(format!(
+                "the minimum flow {} is above the maximum flow {}",
+                self.min_flow, self.max_flow
+            )));
+        }
+        // Layer positions and surface paths step by the nozzle diameter
+        positive("nozzle diameter", self.nozzle_diameter)?;
+        if self.support.mode != SupportMode::None {
+            positive("support spacing", self.support.spacing)?;
+            positive("support gap", self.support.gap)?;
+            if self.support.mode == SupportMode::Tree {
+                positive("branch angle", self.support.branch_angle)?;
+                positive("branch diameter", self.support.branch_diameter)?;
+            }
+        }
+        // Raft layers are filled with lines at these spacings
+        if self.raft.base_layers > 0 {
+            positive("raft base spacing", self.raft.base_spacing)?;
+        }
+        if self.raft.interface_layers > 0 {
+            positive("raft interface spacing", self.raft.interface_spacing)?;
+        }
+        positive("contour snap tolerance", self.contours.snap_tolerance)?;
+        if self.contours.max_gap.is_nan() || self.contours.max_gap < 0.0 {
+            return Err(SliceError::InvalidOptions(format!(
+                "the maximum contour gap {} is negative",
+                self.contours.max_gap
+            )));
+        }
+        if self.tool_change_retract.is_nan() || self.tool_change_retract < 0.0 {
+            return Err(SliceError::InvalidOptions(format!(
+                "the tool change retraction {} is negative",
+                self.tool_change_retract
+            )));
+        }
+        if let Some(tower) = &self.wipe_tower {
+            // Purge lines run inside the outline of the tower, a line width in from its sides
+            let line_width = self.nozzle_diameter;
+            if tower.width.is_nan()
+                || tower.depth.is_nan()
+                || tower.width <= 2.0 * line_width
+                || tower.depth < 3.0 * line_width
+            {
+                return Err(SliceError::InvalidOptions(format!(
+                    "the wipe tower of {} by {} has no room for purge lines",
+                    tower.width, tower.depth
+                )));
+            }
+            if tower.purge_volume.is_nan() || tower.purge_volume < 0.0 {
+                return Err(SliceError::InvalidOptions(format!(
+                    "the purge volume {} is negative",
+                    tower.purge_volume
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rejects values that are zero, negative or not a number
+fn positive(name: &str, value: f64) -> Result<(), SliceError> {
+    if value.is_nan() || value <= 0.0 {
+        return Err(SliceError::InvalidOptions(format!(
+            "the {} {} is not positive",
+            name, value
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct ArcFittingOptions {
+    /// Maximum deviation of the arcs from the paths they replace
+    #[serde(default = "default_arc_tolerance")]
+    pub tolerance: f64,
+    /// Fits helical arcs to non-planar paths, which not every firmware supports
+    #[serde(default)]
+    pub helical: bool,
+}
+
+impl From<ArcFittingOptions> for ArcSettings {
+    fn from(options: ArcFittingOptions) -> Self {
+        Self {
+            tolerance: options.tolerance,
+            helical: options.helical,
+        }
+    }
+}
+
+/// Where the model sits on the bed, applied in order after reading the positions
+#[derive(Tsify, Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PlacementOptions {
+    /// Affine transform of the model in column-major order, applied before the build direction
+    pub transform: Option<[f64; 16]>,
+    /// Index of a triangle that is laid flat onto the bed
+    pub lay_flat_face: Option<usize>,
+    /// Move the lowest point of the model onto the bed.
+    /// By default it is moved whenever the build direction isn't the Z axis,
+    /// which would leave it floating above or sunk into the bed otherwise.
+    pub drop_to_bed: Option<bool>,
+    /// Centre the model on this point of the bed, also dropping it onto the bed
+    pub center: Option<[f64; 2]>,
+}
+
+/// Paint of each triangle of the positions, encoded like the paint of 3MF files
+/// (`paint_seam`, `paint_supports` and `paint_color`, or their `slic3rpe` counterparts).
+/// Missing and empty entries are unpainted.
+#[derive(Tsify, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PaintOptions {
+    pub seam: Vec<String>,
+    pub support: Vec<String>,
+    /// Enforcers and blockers of non-planar surfaces, encoded like support paint
+    pub non_planar: Vec<String>,
+    /// Extruders, counting from 1
+    pub extruder: Vec<String>,
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PlateObject {
+    #[tsify(type = "Float32Array")]
+    pub positions: Vec<f32>,
+    #[serde(default)]
+    pub placement: PlacementOptions,
+    #[serde(default)]
+    pub paint: PaintOptions,
+    /// Modifiers of this object, in the frame of its positions
+    #[serde(default)]
+    pub modifiers: Vec<ModifierOptions>,
+    /// Overrides the layer height of the plate
+    #[serde(default)]
+    pub layer_height: Option<f64>,
+    /// Overrides the maximum angle of non-planar surfaces of the plate
+    #[serde(default)]
+    pub max_angle: Option<f64>,
+    /// Overrides the number of perimeters of the plate
+    #[serde(default)]
+    pub perimeters: Option<usize>,
+    /// Overrides the extruder of the plate
+    #[serde(default)]
+    pub extruder: Option<usize>,
+}
+
+impl PlateObject {
+    /// The options of the plate, with the settings of this object
+    pub fn options(self, plate: &SliceOptions) -> SliceOptions {
+        SliceOptions {
+            positions: self.positions,
+            objects: vec![],
+            placement: self.placement,
+            paint: self.paint,
+            modifiers: self.modifiers,
+            layer_height: self.layer_height.unwrap_or(plate.layer_height),
+            max_angle: self.max_angle.unwrap_or(plate.max_angle),
+            perimeters: self.perimeters.unwrap_or(plate.perimeters),
+            extruder: self.extruder.unwrap_or(plate.extruder),
+            ..plate.clone()
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct WipeTowerOptions {
+    /// Corner of the tower closest to the origin of the bed
+    pub position: [f64; 2],
+    #[serde(default = "default_wipe_tower_size")]
+    pub width: f64,
+    #[serde(default = "default_wipe_tower_size")]
+    pub depth: f64,
+    /// Filament volume purged after every tool change, in mm³
+    #[serde(default = "default_purge_volume")]
+    pub purge_volume: f64,
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase", tag = "type")]
-#[tsify(into_wasm_abi)]
-pub enum Slice {
-    Surface {
+pub enum ModifierVolume {
+    /// A closed mesh
+    Mesh {
         #[tsify(type = "Float32Array")]
-        position: Vec<f32>,
+        positions: Vec<f32>,
     },
-    Ring {
-        #[tsify(type = "Float32Array")]
-        position: Vec<f32>,
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
     },
-    Path {
-        #[tsify(type = "Float32Array")]
-        position: Vec<f32>,
+    Box {
+        center: [f64; 3],
+        size: [f64; 3],
+    },
+    /// A cylinder standing along the Z axis of the model
+    Cylinder {
+        center: [f64; 3],
+        radius: f64,
+        height: f64,
     },
 }
 
+#[derive(Tsify, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ModifierOptions {
+    pub volume: ModifierVolume,
+    /// Forces the surfaces inside the volume to be printed non-planar, or planar,
+    /// regardless of the maximum angle. Surfaces steeper than the toolhead can reach
+    /// without running into them stay planar.
+    #[serde(default)]
+    pub non_planar: Option<bool>,
+    /// Extrusion multiplier of the paths inside the volume
+    #[serde(default)]
+    pub flow: Option<f64>,
+    /// Number of walls of the rings that run mostly inside the volume
+    #[serde(default)]
+    pub perimeters: Option<usize>,
+}
+
+impl From<&ModifierOptions> for ModifierSettings {
+    fn from(options: &ModifierOptions) -> Self {
+        Self {
+            non_planar: options.non_planar,
+            flow: options.flow,
+            perimeters: options.perimeters,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum PlateMode {
+    /// All objects are printed layer by layer together
+    #[default]
+    AllAtOnce,
+    /// Each object is printed completely before the next one, in order
+    #[serde(rename_all = "camelCase")]
+    Sequential {
+        /// Extent of the toolhead around the nozzle as `[min x, min y, max x, max y]`,
+        /// where the minimums are negative
+        toolhead: [f64; 4],
+        /// Height of the gantry above the nozzle
+        gantry_height: f64,
+    },
+}
+
+#[derive(Tsify, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum LayerHeights {
+    /// Every planar layer uses the layer height
+    #[default]
+    Uniform,
+    /// Layer heights derived from the slope and curvature of the planar walls
+    #[serde(rename_all = "camelCase")]
+    Adaptive {
+        min_layer_height: f64,
+        max_layer_height: f64,
+        /// Maximum staircase error of the walls
+        max_cusp_height: f64,
+    },
+    /// Pairs of height above the bottom of the model and layer height,
+    /// linearly interpolated in between
+    #[serde(rename_all = "camelCase")]
+    Profile { profile: Vec<[f64; 2]> },
+}
+
+#[derive(Tsify, Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum SeamPlacement {
+    /// Continue the seam of the closest ring of the previous layer
+    #[default]
+    Aligned,
+    /// Start where the previous ring ended
+    Nearest,
+    /// Start at the back of the model
+    Rear,
+    /// Hide the seam in the sharpest concave corner
+    SharpestCorner,
+    /// Start closest to a point, ignoring its height
+    Point { point: [f64; 3] },
+    /// Start closest to a line segment, ignoring its height
+    Line { start: [f64; 3], end: [f64; 3] },
+}
+
+impl From<SeamPlacement> for Seam {
+    fn from(seam: SeamPlacement) -> Self {
+        match seam {
+            SeamPlacement::Aligned => Seam::Aligned,
+            SeamPlacement::Nearest => Seam::Nearest,
+            SeamPlacement::Rear => Seam::Rear,
+            SeamPlacement::SharpestCorner => Seam::SharpestCorner,
+            SeamPlacement::Point { point } => Seam::Point(point.into()),
+            SeamPlacement::Line { start, end } => Seam::Line(start.into(), end.into()),
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum SurfaceConnectivity {
+    /// Triangles sharing an edge, or surfaces meeting at more than one vertex like along
+    /// T-junctions. Surfaces touching at a single vertex stay apart.
+    #[default]
+    Junction,
+    /// Only triangles sharing an edge
+    Edge,
+}
+
+#[derive(Tsify, Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SurfaceOptions {
+    /// Which triangles are grouped into one surface
+    pub connectivity: SurfaceConnectivity,
+    /// Largest angle between the normals of neighbouring triangles, in radians.
+    /// Surfaces are split into separate patches at sharper creases.
+    pub max_crease_angle: Option<f64>,
+}
+
+impl From<SurfaceOptions> for SplitSettings {
+    fn from(options: SurfaceOptions) -> Self {
+        Self {
+            connectivity: match options.connectivity {
+                SurfaceConnectivity::Junction => Connectivity::Junction,
+                SurfaceConnectivity::Edge => Connectivity::Edge,
+            },
+            max_crease_angle: options.max_crease_angle,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SupportMode {
+    #[default]
+    None,
+    Lines,
+    Grid,
+    /// Branches routed around the model and the toolhead of non-planar surfaces
+    Tree,
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SupportOptions {
+    pub mode: SupportMode,
+    /// Steepest printable overhang, measured from the vertical, in radians
+    pub max_overhang_angle: f64,
+    /// Distance between support lines
+    pub spacing: f64,
+    /// Vertical gap between the support and the model
+    pub gap: f64,
+    /// Maximum lean of tree support branches from the vertical, in radians
+    pub branch_angle: f64,
+    /// Diameter of a single tree support branch
+    pub branch_diameter: f64,
+}
+
+impl Default for SupportOptions {
+    fn default() -> Self {
+        Self {
+            mode: SupportMode::None,
+            max_overhang_angle: std::f64::consts::FRAC_PI_4,
+            spacing: 2.0,
+            gap: 0.2,
+            branch_angle: std::f64::consts::FRAC_PI_4,
+            branch_diameter: 2.0,
+        }
+    }
+}
+
+/// Removes points of the paths that barely change their shape,
+/// as the rings of finely tessellated meshes have a point at every triangle they cross
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SimplifyOptions {
+    /// Maximum distance of the simplified paths from the original ones
+    pub max_deviation: f64,
+    /// Shorter segments are merged into their neighbours
+    pub min_segment_length: f64,
+}
+
+impl Default for SimplifyOptions {
+    fn default() -> Self {
+        Self {
+            max_deviation: 0.01,
+            min_segment_length: 0.05,
+        }
+    }
+}
+
+impl From<SimplifyOptions> for SimplifySettings {
+    fn from(options: SimplifyOptions) -> Self {
+        Self {
+            max_deviation: options.max_deviation,
+            min_segment_length: options.min_segment_length,
+        }
+    }
+}
+
+/// Closing of the contours of meshes that are not watertight
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ContourOptions {
+    /// Line ends closer than this are the same point
+    pub snap_tolerance: f64,
+    /// Open contours whose ends are closer than this are joined with a straight segment,
+    /// zero leaves them open
+    pub max_gap: f64,
+}
+
+impl Default for ContourOptions {
+    fn default() -> Self {
+        Self {
+            snap_tolerance: SNAP_TOLERANCE,
+            max_gap: MAX_GAP,
+        }
+    }
+}
+
+impl From<ContourOptions> for ContourSettings {
+    fn from(options: ContourOptions) -> Self {
+        Self {
+            snap_tolerance: options.snap_tolerance,
+            max_gap: options.max_gap,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SkirtOptions {
+    /// Number of loops, zero disables the skirt
+    pub loops: usize,
+    /// Distance between the skirt and the first layer
+    pub distance: f64,
+}
+
+impl Default for SkirtOptions {
+    fn default() -> Self {
+        Self {
+            loops: 0,
+            distance: 3.0,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BrimOptions {
+    /// Number of loops, zero disables the brim. Ignored when printing a raft.
+    pub loops: usize,
+    /// Only add a brim around the outer contours, not inside holes
+    pub outside_only: bool,
+}
+
+impl Default for BrimOptions {
+    fn default() -> Self {
+        Self {
+            loops: 0,
+            outside_only: true,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RaftOptions {
+    /// Number of sparse base layers
+    pub base_layers: usize,
+    /// Number of dense interface layers, the raft is disabled if both are zero
+    pub interface_layers: usize,
+    /// Distance the raft extends beyond the first layer
+    pub margin: f64,
+    pub base_spacing: f64,
+    pub interface_spacing: f64,
+    /// Vertical gap between the raft and the model
+    pub gap: f64,
+}
+
+impl Default for RaftOptions {
+    fn default() -> Self {
+        Self {
+            base_layers: 0,
+            interface_layers: 0,
+            margin: 3.0,
+            base_spacing: 2.0,
+            interface_spacing: 0.5,
+            gap: 0.1,
+        }
+    }
+}
+
+fn default_optimise_path_order() -> bool {
+    true
+}
+
+fn default_min_flow() -> f64 {
+    0.25
+}
+
+fn default_max_flow() -> f64 {
+    2.0
+}
+
+fn default_filament_diameter() -> f64 {
+    1.75
+}
+
+fn default_print_speed() -> f64 {
+    40.0
+}
+
+fn default_travel_speed() -> f64 {
+    150.0
+}
+
+fn default_arc_tolerance() -> f64 {
+    0.01
+}
+
+fn default_z_hop() -> f64 {
+    0.4
+}
+
+fn default_tool_change_retract() -> f64 {
+    2.0
+}
+
+fn default_perimeters() -> usize {
+    1
+}
+
+fn default_wipe_tower_size() -> f64 {
+    20.0
+}
+
+fn default_purge_volume() -> f64 {
+    70.0
+}
+
+fn default_build_direction() -> [f64; 3] {
+    [0.0, 0.0, 1.0]
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SliceRole {
+    OuterWall,
+    InnerWall,
+    /// Outline of a non-planar surface
+    SurfacePerimeter,
+    /// Fill of a non-planar surface
+    SurfaceFill,
+    Infill,
+    Support,
+    /// Planar lines below unsupported bottom surfaces
+    Bridge,
+    Skirt,
+    Brim,
+    Raft,
+    /// Outlines and purge lines of the wipe tower
+    WipeTower,
+    /// Non-extruding move between two paths
+    Travel,
+}
+
+impl From<PathRole> for SliceRole {
+    fn from(role: PathRole) -> Self {
+        match role {
+            PathRole::OuterWall => SliceRole::OuterWall,
+            PathRole::InnerWall => SliceRole::InnerWall,
+            PathRole::SurfacePerimeter => SliceRole::SurfacePerimeter,
+            PathRole::SurfaceFill => SliceRole::SurfaceFill,
+            PathRole::Infill => SliceRole::Infill,
+            PathRole::Support => SliceRole::Support,
+            PathRole::Bridge => SliceRole::Bridge,
+            PathRole::Skirt => SliceRole::Skirt,
+            PathRole::Brim => SliceRole::Brim,
+            PathRole::Raft => SliceRole::Raft,
+            PathRole::WipeTower => SliceRole::WipeTower,
+            PathRole::Travel => SliceRole::Travel,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[tsify(into_wasm_abi)]
+pub struct Slice {
+    pub role: SliceRole,
+    #[tsify(type = "Float32Array")]
+    pub position: Vec<f32>,
+    /// Extrusion multiplier of each segment
+    #[tsify(type = "Float32Array")]
+    pub flow: Vec<f32>,
+    /// Extrusion width
+    pub width: f64,
+    /// Index of the planar layer the path is printed with, counting raft layers
+    pub layer: usize,
+    /// Index of the dependency level the path is printed in.
+    /// Paths of the same level do not depend on each other.
+    pub dependency: usize,
+    pub closed: bool,
+    /// Index of the non-planar surface the path belongs to
+    pub surface: Option<usize>,
+    /// Index of the extruder the path is printed with
+    pub extruder: usize,
+}
+
+impl Slice {
+    pub fn new(path: ToolPath, dependency: usize) -> Self {
+        Self {
+            role: path.role.into(),
+            position: path
+                .points
+                .into_iter()
+                .flat_map(|point| [point.x as f32, point.y as f32, point.z as f32])
+                .collect(),
+            flow: path.flow.into_iter().map(|flow| flow as f32).collect(),
+            width: path.width,
+            layer: path.layer,
+            dependency,
+            closed: path.closed,
+            surface: path.surface,
+            extruder: path.extruder,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum SliceCollision {
+    /// The bounding boxes of two objects intersect
+    Overlap { objects: [usize; 2] },
+    /// The toolhead hits the first, already printed object while printing the second
+    Toolhead { objects: [usize; 2] },
+    /// An object that is not printed last reaches above the gantry
+    Gantry { object: usize },
+    /// The wipe tower stands in the way of an object, or of the toolhead printing it
+    WipeTower { object: usize },
+}
+
+impl From<Collision> for SliceCollision {
+    fn from(collision: Collision) -> Self {
+        match collision {
+            Collision::Overlap(a, b) => SliceCollision::Overlap { objects: [a, b] },
+            Collision::Toolhead(a, b) => SliceCollision::Toolhead { objects: [a, b] },
+            Collision::Gantry(object) => SliceCollision::Gantry { object },
+            Collision::WipeTower(object) => SliceCollision::WipeTower { object },
+        }
+    }
+}
+
+/// A gap in the contour of a layer, usually caused by a mesh that is not watertight
+#[derive(Tsify, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SliceGap {
+    pub start: [f64; 3],
+    pub end: [f64; 3],
+    /// Index of the planar layer, counting raft layers
+    pub layer: usize,
+    /// Bridged gaps were closed with a straight segment, the others are left open
+    pub bridged: bool,
+}
+
+#[derive(Tsify, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SliceStage {
+    Surfaces,
+    LayerHeights,
+    SurfaceFlow,
+    Walls,
+    Support,
+    Adhesion,
+    Dependencies,
+    PathOrder,
+    Gcode,
+}
+
+/// Emitted while slicing, before the final result is available
+#[derive(Tsify, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+#[tsify(into_wasm_abi)]
+pub enum SliceProgress {
+    Progress {
+        stage: SliceStage,
+        /// Progress within the stage, if it is known
+        percent: Option<f64>,
+        /// The layer currently being processed
+        layer: usize,
+    },
+    /// A resolved dependency level, in the order it will be printed
+    Level {
+        dependency: usize,
+        slices: Vec<Slice>,
+    },
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[tsify(from_wasm_abi)]
+pub struct OrientationOptions {
+    #[tsify(type = "Float32Array")]
+    pub positions: Vec<f32>,
+    pub nozzle_diameter: f64,
+    pub max_angle: f64,
+    #[serde(default)]
+    pub surfaces: SurfaceOptions,
+    /// Steepest overhang that needs no support, measured from the vertical, in radians
+    #[serde(default = "default_max_overhang_angle")]
+    pub max_overhang_angle: f64,
+    /// How much an area of overhang costs compared to the same area of non-planar surface
+    #[serde(default = "default_overhang_weight")]
+    pub overhang_weight: f64,
+    /// Number of build directions sampled over the sphere
+    #[serde(default = "default_orientation_samples")]
+    pub samples: usize,
+    /// Number of orientations returned
+    #[serde(default = "default_orientation_results")]
+    pub results: usize,
+}
+
+impl OrientationOptions {
+    /// Rejects settings that can't be scored, before any work is done
+    pub fn validate(&self) -> Result<(), SliceError> {
+        if self.samples == 0 || self.results == 0 {
+            return Err(SliceError::InvalidOptions(format!(
+                "orientations need at least one sample and one result, got {} and {}",
+                self.samples, self.results
+            )));
+        }
+        // Surfaces smaller than the nozzle are not worth printing non-planar
+        positive("nozzle diameter", self.nozzle_diameter)?;
+        if self.overhang_weight.is_nan() || self.overhang_weight < 0.0 {
+            return Err(SliceError::InvalidOptions(format!(
+                "the overhang weight {} is negative",
+                self.overhang_weight
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn default_max_overhang_angle() -> f64 {
+    std::f64::consts::FRAC_PI_4
+}
+
+fn default_overhang_weight() -> f64 {
+    1.0
+}
+
+fn default_orientation_samples() -> usize {
+    256
+}
+
+fn default_orientation_results() -> usize {
+    5
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Orientation {
+    /// Can be passed as the build direction of the slice options
+    pub build_direction: [f64; 3],
+    /// Quaternion as `[x, y, z, w]` that rotates the model onto the bed
+    pub rotation: [f64; 4],
+    /// Area of the surfaces that are printed non-planar
+    pub surface_area: f64,
+    /// Area of overhangs that need support
+    pub overhang_area: f64,
+    pub score: f64,
+}
+
+impl From<OrientationScore> for Orientation {
+    fn from(score: OrientationScore) -> Self {
+        Self {
+            build_direction: score.build_direction.into(),
+            rotation: score.rotation.coords.into(),
+            surface_area: score.surface_area,
+            overhang_area: score.overhang_area,
+            score: score.score,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[tsify(into_wasm_abi)]
+pub struct OrientationResult {
+    /// Best orientations first
+    pub orientations: Vec<Orientation>,
+}
+
 #[derive(Tsify, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[tsify(into_wasm_abi)]
 pub struct SliceResult {
     pub slices: Vec<Slice>,
+    pub gcode: String,
+    /// Gaps found in the contours of the walls
+    pub gaps: Vec<SliceGap>,
+    /// Objects of the plate that collide with each other or the toolhead
+    pub collisions: Vec<SliceCollision>,
+    /// Total travel distance between paths
+    pub travel_distance: f64,
+    /// Travel distance saved by reordering paths, before they are grouped by extruder
+    pub travel_saved: f64,
+    /// Overhangs the tree support couldn't reach from the bed or the model
+    pub unsupported: Vec<[f64; 3]>,
+}
+
+/// Why slicing stopped without a result
+#[derive(Debug, Clone, PartialEq)]
+pub enum SliceError {
+    Cancelled,
+    /// The options can't be sliced, with a description of the problem
+    InvalidOptions(String),
+}
+
+impl fmt::Display for SliceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SliceError::Cancelled => Cancelled.fmt(f),
+            SliceError::InvalidOptions(reason) => write!(f, "invalid slice options: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SliceError {}
+
+impl From<Cancelled> for SliceError {
+    fn from(_: Cancelled) -> Self {
+        SliceError::Cancelled
+    }
 }
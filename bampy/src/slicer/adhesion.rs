@@ -0,0 +1,293 @@
+use nalgebra::{point, Point2, Point3};
+
+use super::{
+    polygon::{convex_hull, offset, scanline_fill, signed_area},
+    slice_path::SlicePath,
+    toolpath::{PathRole, ToolPath},
+    FloatValue,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RaftSettings {
+    pub base_layers: usize,
+    pub interface_layers: usize,
+    /// Distance the raft extends beyond the first layer
+    pub margin: FloatValue,
+    pub base_spacing: FloatValue,
+    pub interface_spacing: FloatValue,
+    /// Vertical gap between the raft and the model
+    pub gap: FloatValue,
+}
+
+impl RaftSettings {
+    pub fn layers(&self) -> usize {
+        self.base_layers + self.interface_layers
+    }
+
+    /// How far the model is raised by the raft
+    pub fn height(&self, layer_height: FloatValue) -> FloatValue {
+        if self.layers() == 0 {
+            0.0
+        } else {
+            self.layers() as FloatValue * layer_height + self.gap
+        }
+    }
+}
+
+fn to_planar(points: &[Point3<FloatValue>]) -> Vec<Point2<FloatValue>> {
+    points.iter().map(|point| point.xy()).collect()
+}
+
+fn to_path(
+    role: PathRole,
+    polygon: &[Point2<FloatValue>],
+    z: FloatValue,
+    line_width: FloatValue,
+) -> ToolPath {
+    let mut points = polygon
+        .iter()
+        .map(|point| point![point.x, point.y, z])
+        .collect::<Vec<_>>();
+    if let Some(first) = points.first().copied() {
+        points.push(first);
+    }
+    ToolPath {
+        role,
+        width: line_width,
+        ..ToolPath::planar(points, 1.0, true)
+    }
+}
+
+/// The convex hull around all points of the first layer
+pub fn first_layer_hull(first_layer: &[&SlicePath]) -> Vec<Point2<FloatValue>> {
+    convex_hull(
+        &first_layer
+            .iter()
+            .flat_map(|path| path.points.iter().map(|point| point.xy()))
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Loops around the whole first layer, `distance` away from it
+pub fn skirt(
+    hull: &[Point2<FloatValue>],
+    z: FloatValue,
+    loops: usize,
+    distance: FloatValue,
+    line_width: FloatValue,
+) -> Vec<ToolPath> {
+    (0..loops)
+        .rev()
+        .map(|i| {
+            let polygon = offset(hull, distance + i as FloatValue * line_width);
+            to_path(PathRole::Skirt, &polygon, z, line_width)
+        })
+        .collect()
+}
+
+/// Loops around the outer contours of the first layer,
+/// and inside of its holes unless `outside_only` is set.
+pub fn brim(
+    rings: &[&SlicePath],
+    loops: usize,
+    line_width: FloatValue,
+    outside_only: bool,
+) -> Vec<ToolPath> {
+    let mut paths = Vec::new();
+    for (i, ring) in rings.iter().enumerate() {
+        if !ring.closed || ring.points.len() < 4 {
+            continue;
+        }
+        let hole = rings
+            .iter()
+            .enumerate()
+            .filter(|(j, other)| i != *j && other.closed && other.contains(&ring.points[0]))
+            .count()
+            % 2
+            == 1;
+        if hole && outside_only {
+            continue;
+        }
+
+        let polygon = to_planar(&ring.points);
+        let area = signed_area(&polygon);
+        // Rings are clockwise, so outside of the material is on the left of outer contours
+        // and on the right of holes
+        let direction = if hole { -1.0 } else { 1.0 };
+        for k in (1..=loops).rev() {
+            let loop_polygon = offset(&polygon, direction * k as FloatValue * line_width);
+            if hole && signed_area(&loop_polygon).signum() != area.signum() {
+                continue;
+            }
+            paths.push(to_path(PathRole::Brim, &loop_polygon, ring.d, line_width));
+        }
+    }
+    paths
+}
+
+/// Raft layers below the model, each raster filled along alternating axes.
+/// The base layers are sparse and printed first, the interface layers are dense.
+pub fn raft(
+    hull: &[Point2<FloatValue>],
+    bottom: FloatValue,
+    layer_height: FloatValue,
+    line_width: FloatValue,
+    settings: &RaftSettings,
+) -> Vec<Vec<ToolPath>> {
+    let outline = offset(hull, settings.margin);
+    (0..settings.layers())
+        .map(|layer| {
+            let z = bottom + (layer + 1) as FloatValue * layer_height;
+            let spacing = if layer < settings.base_layers {
+                settings.base_spacing
+            } else {
+                settings.interface_spacing
+            };
+            std::iter::once(ToolPath {
+                layer,
+                ..to_path(PathRole::Raft, &outline, z, line_width)
+            })
+            .chain(
+                scanline_fill(&outline, spacing, layer % 2 == 1)
+                    .into_iter()
+                    .map(|(start, end)| ToolPath {
+                        role: PathRole::Raft,
+                        width: line_width,
+                        layer,
+                        ..ToolPath::planar(
+                            vec![point![start.x, start.y, z], point![end.x, end.y, z]],
+                            1.0,
+                            false,
+                        )
+                    }),
+            )
+            .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{point, Point2};
+
+    use crate::slicer::{
+        aabb_from_points,
+        adhesion::{brim, raft, skirt, RaftSettings},
+        axis::Axis,
+        slice_path::SlicePath,
+        toolpath::{PathRole, ToolPath},
+    };
+
+    /// A clockwise square
+    fn square(min: f64, max: f64) -> Vec<Point2<f64>> {
+        vec![
+            point![min, min],
+            point![min, max],
+            point![max, max],
+            point![max, min],
+        ]
+    }
+
+    /// A clockwise rectangle at z = 0.2
+    fn ring(min: Point2<f64>, max: Point2<f64>) -> SlicePath {
+        let mut points = [
+            point![min.x, min.y, 0.2],
+            point![min.x, max.y, 0.2],
+            point![max.x, max.y, 0.2],
+            point![max.x, min.y, 0.2],
+        ]
+        .to_vec();
+        points.push(points[0]);
+        SlicePath {
+            d: 0.2,
+            axis: Axis::Z,
+            closed: true,
+            aabb: aabb_from_points(points.iter()),
+            points,
+            ..Default::default()
+        }
+    }
+
+    fn extent(path: &ToolPath) -> (f64, f64) {
+        let aabb = aabb_from_points(path.points.iter());
+        (aabb.min.x, aabb.max.x)
+    }
+
+    #[test]
+    fn test_skirt() {
+        let paths = skirt(&square(0.0, 10.0), 0.2, 2, 3.0, 0.4);
+
+        assert_eq!(paths.len(), 2);
+        // Printed from the outside in
+        let (min, max) = extent(&paths[0]);
+        assert_relative_eq!(min, -3.4, epsilon = 1e-9);
+        assert_relative_eq!(max, 13.4, epsilon = 1e-9);
+        let (min, _) = extent(&paths[1]);
+        assert_relative_eq!(min, -3.0, epsilon = 1e-9);
+        for path in &paths {
+            assert_eq!(path.role, PathRole::Skirt);
+            assert_eq!(path.points.first(), path.points.last());
+            assert!(path.points.iter().all(|point| point.z == 0.2));
+        }
+    }
+
+    #[test]
+    fn test_brim() {
+        let outer = ring(point![0.0, 0.0], point![10.0, 10.0]);
+        let hole = ring(point![4.0, 4.0], point![6.0, 8.0]);
+
+        let outside = brim(&[&outer, &hole], 2, 0.4, true);
+        assert_eq!(outside.len(), 2);
+        let (min, max) = extent(&outside[0]);
+        assert_relative_eq!(min, -0.8, epsilon = 1e-9);
+        assert_relative_eq!(max, 10.8, epsilon = 1e-9);
+
+        // Loops inside the hole shrink towards its centre
+        let all = brim(&[&outer, &hole], 2, 0.4, false);
+        assert_eq!(all.len(), 4);
+        let (min, max) = extent(&all[2]);
+        assert_relative_eq!(min, 4.8, epsilon = 1e-9);
+        assert_relative_eq!(max, 5.2, epsilon = 1e-9);
+
+        // Loops that would turn the narrow hole inside out are left out
+        assert_eq!(brim(&[&outer, &hole], 3, 0.4, false).len(), 5);
+    }
+
+    #[test]
+    fn test_raft() {
+        let settings = RaftSettings {
+            base_layers: 1,
+            interface_layers: 2,
+            margin: 2.0,
+            base_spacing: 2.0,
+            interface_spacing: 0.5,
+            gap: 0.1,
+        };
+        assert_eq!(settings.layers(), 3);
+        assert_relative_eq!(settings.height(0.2), 0.7);
+
+        let layers = raft(&square(0.0, 10.0), 0.0, 0.2, 0.4, &settings);
+        assert_eq!(layers.len(), 3);
+        for (i, layer) in layers.iter().enumerate() {
+            // Each layer starts with its outline, extending the margin beyond the hull
+            let (min, max) = extent(&layer[0]);
+            assert_relative_eq!(min, -2.0, epsilon = 1e-9);
+            assert_relative_eq!(max, 12.0, epsilon = 1e-9);
+            assert!(layer[0].closed);
+            for path in layer {
+                assert_eq!(path.role, PathRole::Raft);
+                assert_eq!(path.layer, i);
+                assert_relative_eq!(path.points[0].z, 0.2 * (i + 1) as f64);
+            }
+        }
+        // Sparse base lines along X, dense interface lines alternating along Y and X
+        assert!(layers[0].len() < layers[1].len());
+        assert!(layers[0][1..]
+            .iter()
+            .all(|path| path.points[0].y == path.points[1].y));
+        assert!(layers[1][1..]
+            .iter()
+            .all(|path| path.points[0].x == path.points[1].x));
+    }
+}
@@ -0,0 +1,184 @@
+use nalgebra::{Point2, Point3};
+
+use super::FloatValue;
+
+/// Fewest segments replaced by a single arc
+const MIN_ARC_SEGMENTS: usize = 3;
+/// Arcs wider than this are indistinguishable from lines
+const MAX_RADIUS: FloatValue = 1000.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ArcSettings {
+    /// Maximum distance of the points of a path from the arc replacing them
+    pub tolerance: FloatValue,
+    /// Fit helical arcs to segments rising at a constant rate, where the firmware supports it
+    pub helical: bool,
+}
+
+/// A run of segments of a path, printed as a single move
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fit {
+    /// Index of the first point of the run
+    pub start: usize,
+    /// Index of the last point of the run
+    pub end: usize,
+    pub arc: Option<Arc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arc {
+    pub center: Point2<FloatValue>,
+    pub clockwise: bool,
+}
+
+/// The circle through three points in the XY plane
+fn circle(
+    a: &Point3<FloatValue>,
+    b: &Point3<FloatValue>,
+    c: &Point3<FloatValue>,
+) -> Option<(Point2<FloatValue>, FloatValue)> {
+    let (ab, ac) = (b.xy() - a.xy(), c.xy() - a.xy());
+    let det = 2.0 * ab.perp(&ac);
+    if det.abs() < FloatValue::EPSILON {
+        return None;
+    }
+    let center = a.xy()
+        + nalgebra::vector![
+            ac.y * ab.norm_squared() - ab.y * ac.norm_squared(),
+            ab.x * ac.norm_squared() - ac.x * ab.norm_squared()
+        ] / det;
+    let radius = (a.xy() - center).norm();
+    Some((center, radius))
+}
+
+/// Checks if the points lie on a single arc, turning one way by less than a full circle
+fn fit_arc(points: &[Point3<FloatValue>], settings: &ArcSettings) -> Option<Arc> {
+    let (first, last) = (points.first()?, points.last()?);
+    let (center, radius) = circle(first, &points[points.len() / 2], last)?;
+    if radius > MAX_RADIUS {
+        return None;
+    }
+    let turn =
+        |a: &Point3<FloatValue>, b: &Point3<FloatValue>| (a.xy() - center).perp(&(b.xy() - center));
+    let clockwise = turn(first, &points[1]) < 0.0;
+
+    let mut angles = Vec::with_capacity(points.len());
+    let mut angle = 0.0;
+    angles.push(angle);
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0].xy() - center, pair[1].xy() - center);
+        let step = a.perp(&b).atan2(a.dot(&b));
+        if (step < 0.0) != clockwise || step.abs() < FloatValue::EPSILON {
+            return None;
+        }
+        angle += step;
+        angles.push(angle);
+    }
+    if angle.abs() >= 2.0 * std::f64::consts::PI {
+        return None;
+    }
+    let on_circle = points
+        .iter()
+        .all(|point| ((point.xy() - center).norm() - radius).abs() <= settings.tolerance);
+    // Chords bulge away from the arc the most in their middle
+    let chords = points.windows(2).all(|pair| {
+        let middle = (pair[0].xy().coords + pair[1].xy().coords) / 2.0;
+        (radius - (middle - center.coords).norm()) <= settings.tolerance
+    });
+    // Helical arcs rise in proportion to the angle they sweep
+    let rise = last.z - first.z;
+    let height = points.iter().zip(&angles).all(|(point, swept)| {
+        let z = first.z + rise * swept / angle;
+        (point.z - z).abs() <= settings.tolerance
+    });
+    let planar = points.iter().all(|point| point.z == first.z);
+    (on_circle && chords && (planar || settings.helical && height))
+        .then_some(Arc { center, clockwise })
+}
+
+/// Replaces runs of segments lying on a circle within the tolerance with arcs,
+/// leaving the remaining segments as lines.
+pub fn fit_arcs(points: &[Point3<FloatValue>], settings: &ArcSettings) -> Vec<Fit> {
+    let mut fits = Vec::new();
+    let mut start = 0;
+    while start + 1 < points.len() {
+        let mut best = None;
+        let mut end = start + MIN_ARC_SEGMENTS;
+        while end < points.len() {
+            match fit_arc(&points[start..=end], settings) {
+                Some(arc) => best = Some((end, arc)),
+                None => break,
+            }
+            end += 1;
+        }
+        match best {
+            Some((end, arc)) => {
+                fits.push(Fit {
+                    start,
+                    end,
+                    arc: Some(arc),
+                });
+                start = end;
+            }
+            None => {
+                fits.push(Fit {
+                    start,
+                    end: start + 1,
+                    arc: None,
+                });
+                start += 1;
+            }
+        }
+    }
+    fits
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{point, Point3};
+
+    use crate::slicer::arc::{fit_arcs, ArcSettings};
+
+    fn quarter(rise: f64) -> Vec<Point3<f64>> {
+        (0..=16)
+            .map(|i| {
+                let angle = i as f64 / 16.0 * std::f64::consts::FRAC_PI_2;
+                point![10.0 * angle.cos(), 10.0 * angle.sin(), rise * i as f64]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fit_arcs() {
+        // The chords of the quarter circle stray up to 0.012 from it
+        let settings = ArcSettings {
+            tolerance: 0.02,
+            helical: false,
+        };
+        let fits = fit_arcs(&quarter(0.0), &settings);
+        assert_eq!(fits.len(), 1);
+        let arc = fits[0].arc.unwrap();
+        assert!(!arc.clockwise);
+        assert!(arc.center.coords.norm() < 1e-9);
+
+        // A corner is not an arc
+        let corner = vec![
+            point![0.0, 0.0, 0.0],
+            point![1.0, 0.0, 0.0],
+            point![2.0, 0.0, 0.0],
+            point![2.0, 1.0, 0.0],
+            point![2.0, 2.0, 0.0],
+        ];
+        assert!(fit_arcs(&corner, &settings)
+            .iter()
+            .all(|fit| fit.arc.is_none() && fit.end == fit.start + 1));
+
+        // Rising segments stay linear unless helical arcs are allowed
+        assert_eq!(fit_arcs(&quarter(0.1), &settings).len(), 16);
+        let helical = ArcSettings {
+            helical: true,
+            ..settings
+        };
+        assert_eq!(fit_arcs(&quarter(0.1), &helical).len(), 1);
+    }
+}
@@ -1,8 +1,31 @@
+use std::collections::HashMap;
+
 use super::{aabb_from_points, axis::Axis, line::Line3, slice_path::SlicePath, FloatValue};
-use approx::relative_eq;
 use nalgebra::Point3;
 
-#[derive(Debug)]
+/// Line ends closer than this are the same point
+pub const SNAP_TOLERANCE: FloatValue = 1e-6;
+/// Open contours whose ends are closer than this are joined with a straight segment
+pub const MAX_GAP: FloatValue = 0.05;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ContourSettings {
+    /// Line ends closer than this are the same point
+    pub snap_tolerance: FloatValue,
+    /// Open contours whose ends are closer than this are joined with a straight segment
+    pub max_gap: FloatValue,
+}
+
+impl Default for ContourSettings {
+    fn default() -> Self {
+        Self {
+            snap_tolerance: SNAP_TOLERANCE,
+            max_gap: MAX_GAP,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct BaseSlice {
     pub i: usize,
     pub d: FloatValue,
@@ -10,73 +33,421 @@ pub struct BaseSlice {
     pub lines: Vec<Line3>,
 }
 
+/// A gap in a contour, usually caused by a mesh that is not watertight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gap {
+    /// Index of the slice the gap is in
+    pub i: usize,
+    pub start: Point3<FloatValue>,
+    pub end: Point3<FloatValue>,
+    /// Bridged gaps were closed with a straight segment, the others are left open
+    pub bridged: bool,
+}
+
+/// Line ends, hashed by their position on a grid of the snap tolerance
+struct EndpointGrid {
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    axes: (usize, usize),
+    tolerance: FloatValue,
+}
+
+impl EndpointGrid {
+    fn new(lines: &[Line3], axes: (usize, usize), tolerance: FloatValue) -> Self {
+        let mut grid = Self {
+            cells: HashMap::with_capacity(lines.len() * 2),
+            axes,
+            tolerance,
+        };
+        for (i, line) in lines.iter().enumerate() {
+            for (side, point) in [line.start, line.end].iter().enumerate() {
+                grid.cells
+                    .entry(grid.cell(point))
+                    .or_default()
+                    .push(i * 2 + side);
+            }
+        }
+        grid
+    }
+
+    fn cell(&self, point: &Point3<FloatValue>) -> (i64, i64) {
+        (
+            (point[self.axes.0] / self.tolerance).floor() as i64,
+            (point[self.axes.1] / self.tolerance).floor() as i64,
+        )
+    }
+
+    /// The nearest end of an unused line within the snap tolerance,
+    /// as `line * 2 + side` where side 0 is the start of the line
+    fn find(&self, point: &Point3<FloatValue>, lines: &[Line3], used: &[bool]) -> Option<usize> {
+        let (x, y) = self.cell(point);
+        let mut best: Option<(usize, FloatValue)> = None;
+        for cell in (x - 1..=x + 1).flat_map(|x| (y - 1..=y + 1).map(move |y| (x, y))) {
+            for &end in self.cells.get(&cell).into_iter().flatten() {
+                if used[end / 2] {
+                    continue;
+                }
+                let distance = (endpoint(lines, end) - point).norm();
+                if distance <= self.tolerance && best.is_none_or(|(_, d)| distance < d) {
+                    best = Some((end, distance));
+                }
+            }
+        }
+        best.map(|(end, _)| end)
+    }
+
+    /// Appends connected lines to the end of `side` until it reaches `target` or runs out.
+    /// Returns whether the target was reached.
+    fn follow(
+        &self,
+        side: &mut Vec<Point3<FloatValue>>,
+        target: &Point3<FloatValue>,
+        lines: &[Line3],
+        used: &mut [bool],
+    ) -> bool {
+        while let Some(end) = self.find(side.last().unwrap(), lines, used) {
+            used[end / 2] = true;
+            let next = endpoint(lines, end ^ 1);
+            side.push(next);
+            if (next - target).norm() <= self.tolerance {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+fn endpoint(lines: &[Line3], end: usize) -> Point3<FloatValue> {
+    if end.is_multiple_of(2) {
+        lines[end / 2].start
+    } else {
+        lines[end / 2].end
+    }
+}
+
+struct Chain {
+    points: Vec<Point3<FloatValue>>,
+    closed: bool,
+}
+
 impl BaseSlice {
-    pub fn find_paths(mut self) -> Vec<SlicePath> {
+    pub fn find_paths(self) -> Vec<SlicePath> {
+        self.find_paths_with_gaps(&ContourSettings::default()).0
+    }
+
+    /// Chains the lines into rings, bridging small gaps between open ends.
+    ///
+    /// Line ends are matched through hash grids, so this runs in linear time.
+    pub fn find_paths_with_gaps(self, settings: &ContourSettings) -> (Vec<SlicePath>, Vec<Gap>) {
         let (axis_a, axis_b) = self.axis.other();
-        let mut rings = vec![];
-        while let Some(line) = self.lines.pop() {
-            if relative_eq!(line.start, line.end) {
+        let BaseSlice { i, d, axis, lines } = self;
+        let grid = EndpointGrid::new(
+            &lines,
+            (axis_a as usize, axis_b as usize),
+            settings.snap_tolerance,
+        );
+        let mut used = vec![false; lines.len()];
+
+        let mut chains = vec![];
+        for index in (0..lines.len()).rev() {
+            if used[index] {
                 continue;
             }
-            let mut right = vec![line.end];
+            used[index] = true;
+            let line = lines[index];
+            if (line.end - line.start).norm() <= settings.snap_tolerance {
+                continue;
+            }
+
             let mut left = vec![line.start];
-            let mut previous_len = usize::MAX;
-            let mut closed = false;
+            let mut right = vec![line.end];
+            let mut closed = grid.follow(&mut right, &left[0], &lines, &mut used);
+            if !closed {
+                closed = grid.follow(&mut left, right.last().unwrap(), &lines, &mut used);
+            }
+
+            left.reverse();
+            left.extend(right);
+            chains.push(Chain {
+                points: left,
+                closed,
+            });
+        }
 
-            while !closed {
-                if previous_len == self.lines.len() {
-                    break;
+        let gaps = bridge_gaps(&mut chains, i, settings.max_gap);
+
+        let rings = chains
+            .into_iter()
+            .map(|mut chain| {
+                if chain.closed {
+                    let first = chain.points[0];
+                    *chain.points.last_mut().unwrap() = first;
                 }
-                previous_len = self.lines.len();
+                let mut ring = SlicePath {
+                    d,
+                    i,
+                    axis,
+                    closed: chain.closed,
+                    aabb: aabb_from_points(chain.points.iter()),
+                    points: chain.points,
+                };
 
-                self.lines.retain_mut(|line| {
-                    if closed {
-                        return true;
-                    }
+                if ring.points.windows(2).fold(0.0, |acc, curr| {
+                    acc + (curr[1][axis_a as usize] - curr[0][axis_a as usize])
+                        * (curr[1][axis_b as usize] + curr[0][axis_b as usize])
+                }) < 0.0
+                {
+                    ring.points.reverse();
+                }
+                ring
+            })
+            .collect();
 
-                    let test = |side: &mut Vec<Point3<FloatValue>>| {
-                        let last = side.last().unwrap();
-                        let s = relative_eq!(line.start, last);
-                        let e = relative_eq!(line.end, last);
-                        if s && !e {
-                            side.push(line.end);
-                        } else if !s && e {
-                            side.push(line.start);
-                        }
-                        s || e
-                    };
-
-                    if test(&mut left) || test(&mut right) {
-                        closed = relative_eq!(left.last().unwrap(), right.last().unwrap());
-                        false
-                    } else {
-                        true
+        (rings, gaps)
+    }
+}
+
+/// Joins the closest open ends within `max_gap` until none are left,
+/// then reports the ends of the remaining open chains.
+///
+/// Joining two chains leaves their outer ends in place, so the pairs of ends within reach
+/// are found once through a hash grid and joined from the closest one.
+fn bridge_gaps(chains: &mut Vec<Chain>, slice: usize, max_gap: FloatValue) -> Vec<Gap> {
+    let open = (0..chains.len())
+        .filter(|i| !chains[*i].closed)
+        .collect::<Vec<_>>();
+    // The ends of the open chains, as `open * 2 + side` where side 0 is the start of the chain
+    let ends = open
+        .iter()
+        .flat_map(|i| {
+            let points = &chains[*i].points;
+            [points[0], *points.last().unwrap()]
+        })
+        .collect::<Vec<_>>();
+
+    let mut pairs = Vec::<(FloatValue, usize, usize)>::new();
+    if max_gap > 0.0 {
+        let cell = |point: &Point3<FloatValue>| {
+            (
+                (point.x / max_gap).floor() as i64,
+                (point.y / max_gap).floor() as i64,
+                (point.z / max_gap).floor() as i64,
+            )
+        };
+        let mut grid = HashMap::<(i64, i64, i64), Vec<usize>>::with_capacity(ends.len());
+        for (end, point) in ends.iter().enumerate() {
+            let (x, y, z) = cell(point);
+            for neighbour in (x - 1..=x + 1).flat_map(|x| {
+                (y - 1..=y + 1).flat_map(move |y| (z - 1..=z + 1).map(move |z| (x, y, z)))
+            }) {
+                for &other in grid.get(&neighbour).into_iter().flatten() {
+                    // A chain can only close onto itself if it encloses something
+                    if other == end ^ 1 && chains[open[end / 2]].points.len() <= 2 {
+                        continue;
+                    }
+                    let distance = (ends[other] - point).norm();
+                    if distance <= max_gap {
+                        pairs.push((distance, other, end));
                     }
-                })
+                }
             }
+            grid.entry((x, y, z)).or_default().push(end);
+        }
+        pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    }
 
-            left.reverse();
-            left.extend(right);
-            let mut ring = SlicePath {
-                d: self.d,
-                i: self.i,
-                axis: self.axis,
-                closed,
-                aabb: aabb_from_points(left.iter()),
-                points: left,
-            };
+    // The end at the other side of the joined chains each end belongs to
+    let mut opposite = (0..ends.len()).map(|end| end ^ 1).collect::<Vec<_>>();
+    let mut links = vec![None; ends.len()];
+    for (_, a, b) in pairs {
+        if links[a].is_some() || links[b].is_some() {
+            continue;
+        }
+        links[a] = Some(b);
+        links[b] = Some(a);
+        let (a, b) = (opposite[a], opposite[b]);
+        opposite[a] = b;
+        opposite[b] = a;
+    }
 
-            if ring.points.windows(2).fold(0.0, |acc, curr| {
-                acc + (curr[1][axis_a as usize] - curr[0][axis_a as usize])
-                    * (curr[1][axis_b as usize] + curr[0][axis_b as usize])
-            }) < 0.0
-            {
-                ring.points.reverse();
+    let mut gaps = vec![];
+    let mut open_gaps = vec![];
+    let mut visited = vec![false; open.len()];
+    let mut previous = std::mem::take(chains);
+    for i in 0..previous.len() {
+        if previous[i].closed {
+            chains.push(Chain {
+                points: std::mem::take(&mut previous[i].points),
+                closed: true,
+            });
+            continue;
+        }
+        let k = open.binary_search(&i).unwrap();
+        if visited[k] {
+            continue;
+        }
+        // Start at the unlinked end of the joined chains, or anywhere on a closed loop
+        let mut start = k * 2;
+        while let Some(link) = links[start] {
+            start = link ^ 1;
+            if start == k * 2 {
+                break;
             }
+        }
 
-            rings.push(ring);
+        let mut points = vec![];
+        let mut end = start;
+        let closed = loop {
+            visited[end / 2] = true;
+            let mut chain = std::mem::take(&mut previous[open[end / 2]].points);
+            if end % 2 == 1 {
+                chain.reverse();
+            }
+            points.extend(chain);
+            let exit = end ^ 1;
+            let Some(next) = links[exit] else {
+                break false;
+            };
+            gaps.push(Gap {
+                i: slice,
+                start: ends[exit],
+                end: ends[next],
+                bridged: true,
+            });
+            if next == start {
+                points.push(points[0]);
+                break true;
+            }
+            end = next;
+        };
+        if !closed {
+            open_gaps.push(Gap {
+                i: slice,
+                start: *points.last().unwrap(),
+                end: points[0],
+                bridged: false,
+            });
+        }
+        chains.push(Chain { points, closed });
+    }
+    gaps.extend(open_gaps);
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use nalgebra::{point, Point3};
+    use test::Bencher;
+
+    use crate::slicer::{
+        axis::Axis,
+        base_slices::{BaseSlice, ContourSettings},
+        line::Line3,
+        FloatValue,
+    };
+
+    /// Lines around a regular polygon, in shuffled order and direction
+    fn polygon(segments: usize, radius: FloatValue, gap: FloatValue) -> BaseSlice {
+        let point = |i: usize| {
+            let angle = i as FloatValue / segments as FloatValue * std::f64::consts::TAU;
+            point![radius * angle.cos(), radius * angle.sin(), 0.0]
+        };
+        let mut lines = (0..segments)
+            .map(|i| {
+                let end = if i + 1 == segments {
+                    point(0) + (point(0) - Point3::origin()).normalize() * gap
+                } else {
+                    point(i + 1)
+                };
+                if i % 2 == 0 {
+                    Line3::new(point(i), end)
+                } else {
+                    Line3::new(end, point(i))
+                }
+            })
+            .collect::<Vec<_>>();
+        for i in 0..lines.len() {
+            lines.swap(i, (i * 7919) % segments);
+        }
+        BaseSlice {
+            i: 0,
+            d: 0.0,
+            axis: Axis::Z,
+            lines,
         }
+    }
+
+    #[test]
+    fn test_closed_ring() {
+        let (paths, gaps) =
+            polygon(64, 10.0, 0.0).find_paths_with_gaps(&ContourSettings::default());
+
+        assert_eq!(paths.len(), 1);
+        assert!(paths[0].closed);
+        assert_eq!(paths[0].points.len(), 65);
+        assert_eq!(paths[0].points.first(), paths[0].points.last());
+        assert!(gaps.is_empty());
+    }
+
+    #[test]
+    fn test_gaps() {
+        let (paths, gaps) =
+            polygon(64, 10.0, 0.01).find_paths_with_gaps(&ContourSettings::default());
+        assert!(paths[0].closed);
+        assert_eq!(gaps.len(), 1);
+        assert!(gaps[0].bridged);
+
+        let (paths, gaps) =
+            polygon(64, 10.0, 1.0).find_paths_with_gaps(&ContourSettings::default());
+        assert!(!paths[0].closed);
+        assert_eq!(gaps.len(), 1);
+        assert!(!gaps[0].bridged);
+    }
+
+    #[test]
+    fn test_max_gap() {
+        // The sides of a square, each stopping short of the next one
+        let corners = [
+            point![0.0, 0.0, 0.0],
+            point![10.0, 0.0, 0.0],
+            point![10.0, 10.0, 0.0],
+            point![0.0, 10.0, 0.0],
+        ];
+        let lines = (0..4)
+            .map(|i| {
+                let (start, end) = (corners[i], corners[(i + 1) % 4]);
+                Line3::new(start, end - (end - start).normalize() * 0.01)
+            })
+            .collect::<Vec<_>>();
+        let slice = BaseSlice {
+            i: 0,
+            d: 0.0,
+            axis: Axis::Z,
+            lines,
+        };
+
+        let (paths, gaps) = slice
+            .clone()
+            .find_paths_with_gaps(&ContourSettings::default());
+        assert_eq!(paths.len(), 1);
+        assert!(paths[0].closed);
+        assert_eq!(paths[0].points.len(), 9);
+        assert_eq!(gaps.len(), 4);
+        assert!(gaps.iter().all(|gap| gap.bridged));
+
+        let (paths, gaps) = slice.find_paths_with_gaps(&ContourSettings {
+            max_gap: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(paths.len(), 4);
+        assert!(paths.iter().all(|path| !path.closed));
+        assert!(gaps.iter().all(|gap| !gap.bridged));
+    }
 
-        rings
+    #[bench]
+    fn bench_find_paths(b: &mut Bencher) {
+        let slice = polygon(20000, 50.0, 0.0);
+        b.iter(|| slice.clone().find_paths());
     }
 }
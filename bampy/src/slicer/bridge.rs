@@ -0,0 +1,143 @@
+use nalgebra::{point, Point2};
+
+use super::{
+    aabb_from_points, axis::Axis, mesh::Mesh, polygon::scanline_fill_rings, slice_path::SlicePath,
+    support::vertical_hit_triangles, FloatValue,
+};
+
+/// Bridges below bottom surfaces that rest neither on the bed nor on support.
+///
+/// Every part of a surface is bridged on the first layer above it, so the bridges of a sloped
+/// surface step up along it. The lines span the shorter side of the outline,
+/// so they hang free for as short as possible.
+pub fn generate_bridges(
+    surfaces: &[Mesh],
+    layers: &[FloatValue],
+    spacing: FloatValue,
+) -> Vec<SlicePath> {
+    let mut bridges = vec![];
+    let Some(bed) = layers.first() else {
+        return bridges;
+    };
+    for surface in surfaces {
+        let rings = surface
+            .outline_base_slice(Axis::Z)
+            .find_paths()
+            .into_iter()
+            .filter(|path| path.closed)
+            .map(|path| {
+                path.points
+                    .iter()
+                    .map(|point| point.xy())
+                    .collect::<Vec<Point2<FloatValue>>>()
+            })
+            .collect::<Vec<_>>();
+        // The layer bridging the surface at a point, unless it lies on the first layer
+        let layer_at = |point: Point2<FloatValue>| {
+            let (z, _) = *vertical_hit_triangles(surface, point.x, point.y).first()?;
+            let i = layers.partition_point(|layer| *layer <= z);
+            (z > *bed && i < layers.len()).then_some(i)
+        };
+
+        let size = surface.aabb.max - surface.aabb.min;
+        let vertical = size.y < size.x;
+        for (start, end) in scanline_fill_rings(&rings, spacing, vertical) {
+            // Each line is split into steps no longer than half the spacing,
+            // and every run of steps bridged on the same layer becomes a bridge
+            let steps = ((end - start).norm() / (spacing / 2.0)).ceil().max(1.0) as usize;
+            let along = |t: FloatValue| start + (end - start) * t;
+            let mut run: Option<(usize, FloatValue)> = None;
+            for step in 0..=steps {
+                let layer = (step < steps)
+                    .then(|| layer_at(along((step as FloatValue + 0.5) / steps as FloatValue)))
+                    .flatten();
+                let t = step as FloatValue / steps as FloatValue;
+                match run {
+                    Some((i, _)) if Some(i) == layer => continue,
+                    Some((i, from)) => {
+                        let (a, b) = (along(from), along(t));
+                        let points = vec![point![a.x, a.y, layers[i]], point![b.x, b.y, layers[i]]];
+                        bridges.push(SlicePath {
+                            i,
+                            d: layers[i],
+                            axis: Axis::Z,
+                            aabb: aabb_from_points(points.iter()),
+                            points,
+                            closed: false,
+                        });
+                    }
+                    None => {}
+                }
+                run = layer.map(|i| (i, t));
+            }
+        }
+    }
+    bridges
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::point;
+
+    use crate::slicer::{bridge::generate_bridges, mesh::Mesh, triangle::Triangle};
+
+    /// A 10 by 2 rectangle facing down at height `z`, rising by `rise` along its short side
+    fn sloped(z: f64, rise: f64) -> Mesh {
+        let (a, b, c, d) = (
+            point![0.0, 0.0, z],
+            point![10.0, 0.0, z],
+            point![10.0, 2.0, z + rise],
+            point![0.0, 2.0, z + rise],
+        );
+        Mesh::from(vec![Triangle::new(a, c, b), Triangle::new(a, d, c)])
+    }
+
+    fn rectangle(z: f64) -> Mesh {
+        sloped(z, 0.0)
+    }
+
+    #[test]
+    fn test_bridge_span() {
+        let layers = (1..=10).map(|i| i as f64 * 0.2).collect::<Vec<_>>();
+
+        let bridges = generate_bridges(&[rectangle(1.0)], &layers, 0.4);
+        assert_eq!(bridges.len(), 25);
+        assert!(bridges
+            .iter()
+            .all(|bridge| bridge.i == 5 && bridge.points.len() == 2));
+        // Spanning the short side
+        let span = bridges[0].points[1] - bridges[0].points[0];
+        assert!((span.norm() - 2.0).abs() < 1e-9);
+
+        assert!(generate_bridges(&[rectangle(0.0)], &layers, 0.4).is_empty());
+    }
+
+    #[test]
+    fn test_sloped_bridge() {
+        let layers = (0..=10).map(|i| i as f64 * 0.2).collect::<Vec<_>>();
+
+        let bridges = generate_bridges(&[sloped(1.0, 0.5)], &layers, 0.4);
+        let mut steps = bridges.iter().map(|bridge| bridge.i).collect::<Vec<_>>();
+        steps.sort_unstable();
+        steps.dedup();
+        assert_eq!(steps, vec![6, 7, 8]);
+        for bridge in &bridges {
+            // Every part of the surface is bridged on the first layer above it,
+            // give or take a step of half the spacing along the slope
+            for point in &bridge.points {
+                let z = 1.0 + point.y * 0.25;
+                assert!(z <= bridge.d + 0.05 && z > bridge.d - 0.25);
+            }
+        }
+        // The steps of each line still span the whole surface
+        let length = bridges
+            .iter()
+            .filter(|bridge| bridge.points[0].x == bridges[0].points[0].x)
+            .map(|bridge| (bridge.points[1] - bridge.points[0]).norm())
+            .sum::<f64>();
+        assert!((length - 2.0).abs() < 1e-9);
+
+        // Resting on the first layer
+        assert!(generate_bridges(&[rectangle(0.0)], &layers, 0.4).is_empty());
+    }
+}
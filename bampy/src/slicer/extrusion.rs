@@ -0,0 +1,172 @@
+use std::ops::RangeInclusive;
+
+use approx::relative_eq;
+use bvh::{aabb::Aabb, bvh::BvhNode};
+use nalgebra::Point3;
+
+use super::{mesh::Mesh, FloatValue};
+
+fn is_below(aabb: &Aabb<FloatValue, 3>, point: &Point3<FloatValue>) -> bool {
+    aabb.min.z < point.z
+        && point.x >= aabb.min.x
+        && point.x <= aabb.max.x
+        && point.y >= aabb.min.y
+        && point.y <= aabb.max.y
+}
+
+/// Finds the highest point of the mesh directly below the given point
+pub fn height_below(point: &Point3<FloatValue>, mesh: &Mesh) -> Option<FloatValue> {
+    if mesh.bvh.nodes.is_empty() {
+        return None;
+    }
+    let mut height: Option<FloatValue> = None;
+    let mut stack = Vec::<usize>::new();
+    stack.push(0);
+    while let Some(i) = stack.pop() {
+        match mesh.bvh.nodes[i] {
+            BvhNode::Node {
+                parent_index: _,
+                child_l_index,
+                child_l_aabb,
+                child_r_index,
+                child_r_aabb,
+            } => {
+                if is_below(&child_l_aabb, point) {
+                    stack.push(child_l_index);
+                }
+                if is_below(&child_r_aabb, point) {
+                    stack.push(child_r_index);
+                }
+            }
+            BvhNode::Leaf {
+                parent_index: _,
+                shape_index,
+            } => {
+                if let Some(z) = mesh.triangles[shape_index].z_at(point.x, point.y) {
+                    if z < point.z
+                        && !relative_eq!(z, point.z)
+                        && height.is_none_or(|height| z > height)
+                    {
+                        height = Some(z);
+                    }
+                }
+            }
+        }
+    }
+    height
+}
+
+/// The vertical distance between a point and the material deposited below it,
+/// which is either the closest planar layer or an already printed surface.
+/// Below the first layer there is only the `bottom` of the model.
+pub fn layer_gap(
+    point: &Point3<FloatValue>,
+    layers: &[FloatValue],
+    surfaces: &[&Mesh],
+    bottom: FloatValue,
+) -> FloatValue {
+    let below = layers.partition_point(|layer| *layer < point.z && !relative_eq!(*layer, point.z));
+    let mut floor = match below.checked_sub(1) {
+        Some(i) => layers[i],
+        None => bottom,
+    };
+    for surface in surfaces {
+        if let Some(height) = height_below(point, surface) {
+            floor = floor.max(height);
+        }
+    }
+    (point.z - floor).max(0.0)
+}
+
+/// Computes the extrusion multiplier of each segment of a non-planar path
+/// from the gap to the material below it, relative to a planar layer.
+pub fn segment_flow(
+    path: &[Point3<FloatValue>],
+    layers: &[FloatValue],
+    surfaces: &[&Mesh],
+    bottom: FloatValue,
+    layer_height: FloatValue,
+    range: &RangeInclusive<FloatValue>,
+) -> Vec<FloatValue> {
+    path.iter()
+        .map(|point| layer_gap(point, layers, surfaces, bottom))
+        .collect::<Vec<_>>()
+        .windows(2)
+        .map(|pair| {
+            ((pair[0] + pair[1]) / (2.0 * layer_height)).clamp(*range.start(), *range.end())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::point;
+
+    use crate::slicer::{
+        extrusion::{layer_gap, segment_flow},
+        mesh::Mesh,
+        triangle::Triangle,
+    };
+
+    #[test]
+    fn test_segment_flow() {
+        let layers = vec![0.0, 0.2, 0.4, 0.6];
+        // A surface at z = 0.8 covering x < 5
+        let surface = Mesh::from(vec![
+            Triangle::new(
+                point![0.0, 0.0, 0.8],
+                point![5.0, 0.0, 0.8],
+                point![5.0, 5.0, 0.8],
+            ),
+            Triangle::new(
+                point![0.0, 0.0, 0.8],
+                point![5.0, 5.0, 0.8],
+                point![0.0, 5.0, 0.8],
+            ),
+        ]);
+        let surfaces = vec![&surface];
+
+        // Above the surface the gap is measured to it, elsewhere to the layer below
+        assert_relative_eq!(
+            layer_gap(&point![2.0, 2.0, 1.0], &layers, &surfaces, 0.0),
+            0.2
+        );
+        assert_relative_eq!(
+            layer_gap(&point![8.0, 2.0, 1.0], &layers, &surfaces, 0.0),
+            0.4
+        );
+        // Points on a layer rest on the layer below it
+        assert_relative_eq!(layer_gap(&point![8.0, 2.0, 0.4], &layers, &[], 0.0), 0.2);
+
+        let path = vec![
+            point![2.0, 2.0, 1.0],
+            point![8.0, 2.0, 1.0],
+            point![8.0, 2.0, 1.8],
+        ];
+        let flow = segment_flow(&path, &layers, &surfaces, 0.0, 0.2, &(0.25..=2.0));
+        assert_eq!(flow.len(), 2);
+        assert_relative_eq!(flow[0], 1.5);
+        // Clamped to the maximum flow
+        assert_relative_eq!(flow[1], 2.0);
+    }
+
+    #[test]
+    fn test_below_first_layer() {
+        // A surface dipping below the first planar layer rests on the bottom of the model
+        let layers = vec![0.3, 0.5];
+        assert_relative_eq!(layer_gap(&point![0.0, 0.0, 0.2], &layers, &[], 0.0), 0.2);
+        assert_relative_eq!(layer_gap(&point![0.0, 0.0, 0.3], &layers, &[], 0.0), 0.3);
+        // Without any planar layers as well
+        assert_relative_eq!(layer_gap(&point![0.0, 0.0, 1.2], &[], &[], 1.0), 0.2);
+        let flow = segment_flow(
+            &[point![0.0, 0.0, 0.2], point![1.0, 0.0, 0.2]],
+            &layers,
+            &[],
+            0.0,
+            0.2,
+            &(0.25..=2.0),
+        );
+        assert_relative_eq!(flow[0], 1.0);
+    }
+}
@@ -0,0 +1,120 @@
+use nalgebra::{Affine3, Point3, Rotation3, Vector3};
+
+use super::{toolpath::ToolPath, FloatValue};
+
+/// The rotation that points the build direction up the Z axis,
+/// if the build direction has a length
+pub fn build_rotation(build_direction: &Vector3<FloatValue>) -> Option<Rotation3<FloatValue>> {
+    let length = build_direction.norm();
+    if length.is_nan() || length <= 0.0 {
+        return None;
+    }
+    // Only a build direction pointing straight down has no shortest rotation
+    Some(
+        Rotation3::rotation_between(build_direction, &Vector3::z()).unwrap_or_else(|| {
+            Rotation3::from_axis_angle(&Vector3::x_axis(), std::f64::consts::PI)
+        }),
+    )
+}
+
+/// Transforms the model into a frame where the build direction points up the Z axis
+/// and the model sits where it is printed.
+///
+/// Slicing happens in this bed frame, and so does the G-code.
+/// Toolpaths for the preview are transformed back to line up with the model.
+#[derive(Debug, Clone, Copy)]
+pub struct BuildFrame {
+    to_bed: Affine3<FloatValue>,
+    to_model: Affine3<FloatValue>,
+}
+
+impl BuildFrame {
+    pub fn new(build_direction: &Vector3<FloatValue>) -> Option<Self> {
+        Self::default().then(&nalgebra::convert(build_rotation(build_direction)?))
+    }
+
+    /// Applies another transform after the ones of this frame,
+    /// unless the combined transform can't be undone
+    pub fn then(self, transform: &Affine3<FloatValue>) -> Option<Self> {
+        let to_bed = transform * self.to_bed;
+        Some(Self {
+            to_bed,
+            to_model: to_bed.try_inverse()?,
+        })
+    }
+
+    /// The transform from the model onto the bed
+    pub fn transform(&self) -> &Affine3<FloatValue> {
+        &self.to_bed
+    }
+
+    pub fn to_bed(self, point: &Point3<FloatValue>) -> Point3<FloatValue> {
+        self.to_bed * point
+    }
+
+    pub fn to_model(self, point: &Point3<FloatValue>) -> Point3<FloatValue> {
+        self.to_model * point
+    }
+
+    pub fn path_to_model(&self, mut path: ToolPath) -> ToolPath {
+        for point in &mut path.points {
+            *point = self.to_model(point);
+        }
+        path
+    }
+}
+
+impl Default for BuildFrame {
+    fn default() -> Self {
+        Self {
+            to_bed: Affine3::identity(),
+            to_model: Affine3::identity(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{point, vector, Affine3, Matrix4};
+
+    use crate::slicer::frame::BuildFrame;
+
+    #[test]
+    fn test_round_trip() {
+        for direction in [
+            vector![0.0, 0.0, 1.0],
+            vector![1.0, 0.0, 0.0],
+            vector![0.0, 0.0, -2.0],
+        ] {
+            let frame = BuildFrame::new(&direction).unwrap();
+            let up = frame.to_bed(&(direction.normalize().into()));
+            assert_relative_eq!(up, point![0.0, 0.0, 1.0], epsilon = 1e-9);
+
+            let point = point![1.0, 2.0, 3.0];
+            assert_relative_eq!(frame.to_model(&frame.to_bed(&point)), point, epsilon = 1e-9);
+        }
+        assert!(BuildFrame::new(&vector![0.0, 0.0, 0.0]).is_none());
+    }
+
+    #[test]
+    fn test_then() {
+        let mirror = Affine3::from_matrix_unchecked(Matrix4::new_nonuniform_scaling(&vector![
+            -2.0, 1.0, 1.0
+        ]));
+        let frame = BuildFrame::new(&vector![0.0, 0.0, 1.0])
+            .unwrap()
+            .then(&mirror)
+            .unwrap();
+        assert_relative_eq!(frame.to_bed(&point![1.0, 2.0, 3.0]), point![-2.0, 2.0, 3.0]);
+        assert_relative_eq!(
+            frame.to_model(&point![-2.0, 2.0, 3.0]),
+            point![1.0, 2.0, 3.0]
+        );
+
+        let flatten = Affine3::from_matrix_unchecked(Matrix4::new_nonuniform_scaling(&vector![
+            1.0, 1.0, 0.0
+        ]));
+        assert!(frame.then(&flatten).is_none());
+    }
+}
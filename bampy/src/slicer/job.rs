@@ -0,0 +1,90 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc,
+    },
+};
+
+use js_sys::{Atomics, Int32Array};
+
+thread_local! {
+    /// A flag shared with JS, set to a non-zero value to cancel the running job.
+    /// JS values can't leave their thread, so the thread that started the job
+    /// forwards it to the job whenever it checks for cancellation.
+    static SIGNAL: RefCell<Option<Int32Array>> = const { RefCell::new(None) };
+}
+
+/// Returned by anything that was stopped through its [`Job`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "slicing was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// A handle to a running slice, used to cancel it cooperatively.
+///
+/// Clones share their state, so a job can be cancelled from another thread
+/// and every thread working on it sees the cancellation.
+#[derive(Debug, Clone, Default)]
+pub struct Job {
+    cancelled: Arc<AtomicI32>,
+}
+
+impl Job {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(1, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        if self.cancelled.load(Ordering::Relaxed) != 0 {
+            return true;
+        }
+        let signalled = SIGNAL.with(|signal| {
+            signal
+                .borrow()
+                .as_ref()
+                .is_some_and(|signal| Atomics::load(signal, 0).is_ok_and(|value| value != 0))
+        });
+        if signalled {
+            self.cancel();
+        }
+        signalled
+    }
+
+    /// The flag of the job, non-zero once it is cancelled.
+    /// It lives in the memory of the module, so when that is shared between threads
+    /// JS can set it directly.
+    pub fn flag(&self) -> &AtomicI32 {
+        &self.cancelled
+    }
+
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Runs `f` while every job on this thread also observes the JS `signal`
+pub fn with_signal<F, T>(signal: Option<Int32Array>, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = SIGNAL.with(|current| current.replace(signal));
+    let result = f();
+    SIGNAL.with(|current| current.replace(previous));
+    result
+}
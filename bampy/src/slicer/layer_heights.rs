@@ -0,0 +1,206 @@
+use approx::relative_eq;
+use bvh::bvh::BvhNode;
+
+use super::{axis::Axis, mesh::Mesh, FloatValue};
+
+/// The range of slopes (as the absolute normal component along the axis)
+/// of the triangles overlapping the slab between `from` and `to`
+fn slope_range(
+    mesh: &Mesh,
+    axis: Axis,
+    from: FloatValue,
+    to: FloatValue,
+) -> Option<(FloatValue, FloatValue)> {
+    let mut range: Option<(FloatValue, FloatValue)> = None;
+    if mesh.bvh.nodes.is_empty() {
+        return range;
+    }
+    let overlaps = |min: FloatValue, max: FloatValue| min <= to && max >= from;
+    let mut stack = Vec::<usize>::with_capacity(mesh.bvh.nodes.len());
+    stack.push(0);
+    while let Some(i) = stack.pop() {
+        match mesh.bvh.nodes[i] {
+            BvhNode::Node {
+                parent_index: _,
+                child_l_index,
+                child_l_aabb,
+                child_r_index,
+                child_r_aabb,
+            } => {
+                if overlaps(
+                    child_l_aabb.min[axis as usize],
+                    child_l_aabb.max[axis as usize],
+                ) {
+                    stack.push(child_l_index);
+                }
+                if overlaps(
+                    child_r_aabb.min[axis as usize],
+                    child_r_aabb.max[axis as usize],
+                ) {
+                    stack.push(child_r_index);
+                }
+            }
+            BvhNode::Leaf {
+                parent_index: _,
+                shape_index,
+            } => {
+                let triangle = &mesh.triangles[shape_index];
+                if overlaps(
+                    triangle.aabb.min[axis as usize],
+                    triangle.aabb.max[axis as usize],
+                ) {
+                    let slope = triangle.normal[axis as usize].abs();
+                    range = Some(range.map_or((slope, slope), |(min, max)| {
+                        (min.min(slope), max.max(slope))
+                    }));
+                }
+            }
+        }
+    }
+    range
+}
+
+/// Computes layer positions that keep the staircase error ("cusp height")
+/// of the given mesh below `max_cusp_height`.
+///
+/// Shallow walls get thinner layers, steep walls thicker ones.
+/// Each layer is checked over its whole thickness, so a surface curving
+/// towards a shallower slope within the layer shrinks it as well.
+/// Where the wall curves, the layer also misses the bulge of the wall between its edges,
+/// by about `h Δ / 8` for a layer of height `h` across which the wall turns by the angle `Δ`.
+/// All heights must be positive, with `min_layer_height <= max_layer_height`.
+pub fn adaptive_layer_positions(
+    mesh: &Mesh,
+    axis: Axis,
+    bottom: FloatValue,
+    top: FloatValue,
+    min_layer_height: FloatValue,
+    max_layer_height: FloatValue,
+    max_cusp_height: FloatValue,
+) -> Vec<FloatValue> {
+    let allowed_height = |from: FloatValue, to: FloatValue| {
+        let Some((min, max)) = slope_range(mesh, axis, from, to) else {
+            return max_layer_height;
+        };
+        let curvature = (min.min(1.0).acos() - max.min(1.0).acos()) / 8.0;
+        (max_cusp_height / (max + curvature)).clamp(min_layer_height, max_layer_height)
+    };
+
+    let mut layers = vec![bottom];
+    let mut layer = bottom;
+    loop {
+        let mut height = max_layer_height;
+        loop {
+            let allowed = allowed_height(layer, layer + height);
+            if allowed >= height || relative_eq!(allowed, height) {
+                break;
+            }
+            height = allowed;
+        }
+        layer += height;
+        if layer > top && !relative_eq!(layer, top) {
+            break;
+        }
+        layers.push(layer);
+    }
+    layers
+}
+
+/// Computes layer positions from pairs of height above `bottom` and layer height,
+/// linearly interpolating between them.
+pub fn profile_layer_positions(
+    bottom: FloatValue,
+    top: FloatValue,
+    profile: &[[FloatValue; 2]],
+    layer_height: FloatValue,
+) -> Vec<FloatValue> {
+    let height_at = |z: FloatValue| {
+        let next = profile.partition_point(|[height, _]| *height <= z);
+        let height = match (profile.get(next.wrapping_sub(1)), profile.get(next)) {
+            (Some([z0, h0]), Some([z1, h1])) => h0 + (h1 - h0) * (z - z0) / (z1 - z0),
+            (Some([_, h]), None) | (None, Some([_, h])) => *h,
+            (None, None) => layer_height,
+        };
+        if height > 0.0 {
+            height
+        } else {
+            layer_height
+        }
+    };
+
+    let mut layers = vec![bottom];
+    let mut layer = bottom;
+    loop {
+        layer += height_at(layer - bottom);
+        if layer > top && !relative_eq!(layer, top) {
+            break;
+        }
+        layers.push(layer);
+    }
+    layers
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use nalgebra::point;
+
+    use crate::slicer::{
+        axis::Axis,
+        layer_heights::{adaptive_layer_positions, profile_layer_positions},
+        mesh::Mesh,
+        triangle::Triangle,
+    };
+
+    #[test]
+    fn test_adaptive_layer_positions() {
+        // A wall at 45° up to z = 1, continuing vertically up to z = 2
+        let mesh = Mesh::from(vec![
+            Triangle::new(
+                point![0.0, 0.0, 0.0],
+                point![1.0, 0.0, 0.0],
+                point![0.0, 1.0, 1.0],
+            ),
+            Triangle::new(
+                point![0.0, 0.0, 1.0],
+                point![1.0, 0.0, 1.0],
+                point![0.0, 0.0, 2.0],
+            ),
+        ]);
+        let layers = adaptive_layer_positions(&mesh, Axis::Z, 0.0, 2.0, 0.05, 0.3, 0.1);
+
+        let sloped = 0.1 * std::f64::consts::SQRT_2;
+        let heights = layers
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect::<Vec<_>>();
+        assert_eq!(heights.len(), 11);
+        for height in &heights[..5] {
+            assert_relative_eq!(*height, sloped, epsilon = 1e-9);
+        }
+        // Layers that would reach across the kink, where the wall turns by 45°, are thinner
+        let kink = 0.1 / (std::f64::consts::FRAC_1_SQRT_2 + std::f64::consts::FRAC_PI_4 / 8.0);
+        for height in &heights[5..8] {
+            assert_relative_eq!(*height, kink, epsilon = 1e-9);
+        }
+        for height in &heights[8..] {
+            assert_relative_eq!(*height, 0.3, epsilon = 1e-9);
+        }
+
+        // Layers never get thinner than the minimum
+        let layers = adaptive_layer_positions(&mesh, Axis::Z, 0.0, 1.0, 0.2, 0.3, 0.01);
+        assert_eq!(layers.len(), 6);
+    }
+
+    #[test]
+    fn test_profile_layer_positions() {
+        let layers = profile_layer_positions(1.0, 2.0, &[[0.0, 0.1], [0.4, 0.3]], 0.2);
+
+        let expected = [1.0, 1.1, 1.25, 1.475, 1.775];
+        assert_eq!(layers.len(), expected.len());
+        for (layer, expected) in layers.iter().zip(expected) {
+            assert_relative_eq!(*layer, expected, epsilon = 1e-9);
+        }
+    }
+}
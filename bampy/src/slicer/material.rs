@@ -0,0 +1,523 @@
+use bvh::aabb::Aabb;
+use nalgebra::{point, Point2, Point3};
+
+use super::{
+    aabb_from_points,
+    mesh::Mesh,
+    paint::PAINT_TOLERANCE,
+    perimeters::outline,
+    slice_path::SlicePath,
+    toolpath::{PathRole, ToolPath},
+    FloatValue,
+};
+
+/// Height difference within which planar paths share a layer of the wipe tower
+const LAYER_TOLERANCE: FloatValue = 1e-6;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WipeTowerSettings {
+    /// Corner of the tower closest to the origin of the bed
+    pub position: Point2<FloatValue>,
+    pub width: FloatValue,
+    pub depth: FloatValue,
+    /// Filament volume purged on the tower after every tool change, in mm³
+    pub purge_volume: FloatValue,
+    pub line_width: FloatValue,
+    /// Layer height the flow of the tower is relative to
+    pub layer_height: FloatValue,
+}
+
+impl WipeTowerSettings {
+    /// The space taken up by a tower of the given height
+    pub fn bounds(&self, height: FloatValue) -> Aabb<FloatValue, 3> {
+        Aabb::with_bounds(
+            point![self.position.x, self.position.y, 0.0],
+            point![
+                self.position.x + self.width,
+                self.position.y + self.depth,
+                height
+            ],
+        )
+    }
+}
+
+/// The extruder painted onto the triangle a point lies on, or the `default` extruder
+fn painted_extruder(painted: &Mesh, point: &Point3<FloatValue>, default: usize) -> usize {
+    painted
+        .triangle_at(point, PAINT_TOLERANCE)
+        .and_then(|triangle| triangle.attributes.extruder)
+        .unwrap_or(default)
+}
+
+/// Splits a closed ring into open runs of a single extruder where it crosses painted triangles.
+/// Segments are assigned to the triangle they lie on, unpainted ones keep the `default` extruder.
+pub fn split_by_extruder(
+    ring: SlicePath,
+    painted: Option<&Mesh>,
+    default: usize,
+) -> Vec<(usize, SlicePath)> {
+    let Some(painted) = painted else {
+        return vec![(default, ring)];
+    };
+    let extruders = ring
+        .points
+        .windows(2)
+        .map(|segment| {
+            let middle = Point3::from((segment[0].coords + segment[1].coords) / 2.0);
+            painted_extruder(painted, &middle, default)
+        })
+        .collect::<Vec<_>>();
+    split_runs(ring, &extruders, default)
+}
+
+/// Splits the inner walls of a ring into runs like the ring itself.
+/// The inner walls are offset from the outline of the ring, each of their segments
+/// takes the extruder of the outline segment across from it.
+pub fn split_walls_by_extruder(
+    walls: Vec<SlicePath>,
+    ring: &SlicePath,
+    painted: Option<&Mesh>,
+    default: usize,
+) -> Vec<(usize, SlicePath)> {
+    let Some(painted) = painted else {
+        return walls.into_iter().map(|wall| (default, wall)).collect();
+    };
+    let outline = outline(ring);
+    let extruders = (0..outline.len())
+        .map(|i| {
+            let middle = (outline[i].coords + outline[(i + 1) % outline.len()].coords) / 2.0;
+            painted_extruder(painted, &point![middle.x, middle.y, ring.d], default)
+        })
+        .collect::<Vec<_>>();
+    walls
+        .into_iter()
+        .flat_map(|wall| {
+            // Walls that don't match the outline keep the default extruder
+            if wall.points.len() != outline.len() + 1 {
+                return vec![(default, wall)];
+            }
+            split_runs(wall, &extruders, default)
+        })
+        .collect()
+}
+
+/// Splits a closed ring into open runs of the extruders of its segments
+fn split_runs(ring: SlicePath, extruders: &[usize], default: usize) -> Vec<(usize, SlicePath)> {
+    let Some(start) = (0..extruders.len())
+        .find(|i| extruders[*i] != extruders[(i + extruders.len() - 1) % extruders.len()])
+    else {
+        return vec![(extruders.first().copied().unwrap_or(default), ring)];
+    };
+
+    let segments = extruders.len();
+    let mut runs = Vec::<(usize, SlicePath)>::new();
+    for k in 0..segments {
+        let i = (start + k) % segments;
+        let end = ring.points[i + 1];
+        match runs.last_mut() {
+            Some((extruder, run)) if *extruder == extruders[i] => run.points.push(end),
+            _ => runs.push((
+                extruders[i],
+                SlicePath {
+                    i: ring.i,
+                    d: ring.d,
+                    axis: ring.axis,
+                    points: vec![ring.points[i], end],
+                    closed: false,
+                    aabb: Aabb::empty(),
+                },
+            )),
+        }
+    }
+    for (_, run) in runs.iter_mut() {
+        run.aabb = aabb_from_points(run.points.iter());
+    }
+    runs
+}
+
+/// Orders the paths of each level by extruder, starting with the one that is already loaded.
+/// The order within the paths of each extruder is kept.
+pub fn group_extruders(levels: Vec<Vec<ToolPath>>) -> Vec<Vec<ToolPath>> {
+    let mut current = None;
+    levels
+        .into_iter()
+        .map(|mut level| {
+            level.sort_by_key(|path| (Some(path.extruder) != current, path.extruder));
+            if let Some(last) = level.last() {
+                current = Some(last.extruder);
+            }
+            level
+        })
+        .collect()
+}
+
+fn is_planar(path: &ToolPath) -> bool {
+    path.surface.is_none() && path.role != PathRole::Travel && !path.points.is_empty()
+}
+
+struct WipeTower<'a> {
+    settings: &'a WipeTowerSettings,
+    top: Option<FloatValue>,
+    thickness: FloatValue,
+    rows: usize,
+}
+
+impl WipeTower<'_> {
+    /// Starts a new layer of the tower with its outline
+    fn layer(&mut self, z: FloatValue, extruder: usize, layer: usize) -> ToolPath {
+        self.thickness = self.top.map_or(self.settings.layer_height, |top| z - top);
+        self.top = Some(z);
+        self.rows = 0;
+        let (min, width, depth) = (
+            self.settings.position,
+            self.settings.width,
+            self.settings.depth,
+        );
+        let points = vec![
+            point![min.x, min.y, z],
+            point![min.x, min.y + depth, z],
+            point![min.x + width, min.y + depth, z],
+            point![min.x + width, min.y, z],
+            point![min.x, min.y, z],
+        ];
+        ToolPath {
+            role: PathRole::WipeTower,
+            width: self.settings.line_width,
+            layer,
+            extruder,
+            ..ToolPath::planar(points, self.thickness / self.settings.layer_height, true)
+        }
+    }
+
+    /// Grows the tower up to `z`. Where the planar layers skip ahead, the gap is filled
+    /// with evenly spaced layers no thicker than the layer height.
+    fn grow(&mut self, z: FloatValue, extruder: usize, layer: usize) -> Vec<ToolPath> {
+        let Some(top) = self.top else {
+            return vec![self.layer(z, extruder, layer)];
+        };
+        let steps = ((z - top - LAYER_TOLERANCE) / self.settings.layer_height)
+            .ceil()
+            .max(1.0) as usize;
+        (1..=steps)
+            .map(|step| {
+                let z = top + (z - top) * step as FloatValue / steps as FloatValue;
+                self.layer(z, extruder, layer)
+            })
+            .collect()
+    }
+
+    /// Purges a new extruder with lines across the tower, continuing where the last purge stopped.
+    /// Once the layer of the tower is full, the purge goes on on a new layer above it,
+    /// so every tool change purges the full volume.
+    fn purge(&mut self, extruder: usize, layer: usize) -> Vec<ToolPath> {
+        let settings = self.settings;
+        let Some(mut z) = self.top else {
+            return vec![];
+        };
+        let inset = settings.line_width;
+        let length = settings.width - 2.0 * inset;
+        let capacity = ((settings.depth - 2.0 * inset) / settings.line_width).floor() as usize;
+        if length <= 0.0 || capacity == 0 {
+            return vec![];
+        }
+
+        let mut paths = Vec::new();
+        let mut volume = settings.purge_volume;
+        while volume > 0.0 {
+            if self.rows >= capacity {
+                z += settings.layer_height;
+                paths.push(self.layer(z, extruder, layer));
+            }
+            let row_volume = length * settings.line_width * self.thickness;
+            let rows = ((volume / row_volume).ceil() as usize).min(capacity - self.rows);
+            let mut points = Vec::with_capacity(rows * 2);
+            for row in self.rows..self.rows + rows {
+                let y =
+                    settings.position.y + inset + (row as FloatValue + 0.5) * settings.line_width;
+                let (start, end) = (
+                    settings.position.x + inset,
+                    settings.position.x + inset + length,
+                );
+                let (start, end) = if row % 2 == 0 {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                points.push(point![start, y, z]);
+                points.push(point![end, y, z]);
+            }
+            self.rows += rows;
+            volume -= rows as FloatValue * row_volume;
+            paths.push(ToolPath {
+                role: PathRole::WipeTower,
+                width: settings.line_width,
+                layer,
+                extruder,
+                ..ToolPath::planar(points, self.thickness / settings.layer_height, false)
+            });
+        }
+        paths
+    }
+}
+
+/// Adds a wipe tower to paths in print order, purging the new extruder on it after each tool change.
+///
+/// The tower grows with the planar layers until the last tool change, every layer gets an outline
+/// so the purge lines stay supported, and layers the planar paths skip are filled in.
+/// Tool changes on non-planar surfaces purge on the current layer, and purges that don't fit
+/// onto it grow the tower ahead of the planar layers.
+/// Returns the levels along with the height of the tower.
+pub fn insert_wipe_tower(
+    levels: Vec<Vec<ToolPath>>,
+    settings: &WipeTowerSettings,
+) -> (Vec<Vec<ToolPath>>, FloatValue) {
+    let extruders = levels
+        .iter()
+        .flatten()
+        .filter(|path| !path.points.is_empty())
+        .map(|path| path.extruder)
+        .collect::<Vec<_>>();
+    let Some(last_change) = (1..extruders.len())
+        .rev()
+        .find(|i| extruders[*i] != extruders[i - 1])
+    else {
+        return (levels, 0.0);
+    };
+
+    let mut tower = WipeTower {
+        settings,
+        top: None,
+        thickness: settings.layer_height,
+        rows: 0,
+    };
+    let mut current = extruders[0];
+    let mut k = 0;
+    let levels = levels
+        .into_iter()
+        .map(|level| {
+            let mut paths = Vec::with_capacity(level.len());
+            for path in level {
+                if path.points.is_empty() {
+                    paths.push(path);
+                    continue;
+                }
+                if k <= last_change && is_planar(&path) {
+                    let z = path.points[0].z;
+                    if tower.top.is_none_or(|top| z > top + LAYER_TOLERANCE) {
+                        paths.extend(tower.grow(z, current, path.layer));
+                    }
+                }
+                if path.extruder != current {
+                    if tower.top.is_none() {
+                        let z = path
+                            .points
+                            .iter()
+                            .map(|point| point.z)
+                            .fold(FloatValue::MAX, FloatValue::min);
+                        paths.push(tower.layer(z, current, path.layer));
+                    }
+                    paths.extend(tower.purge(path.extruder, path.layer));
+                    current = path.extruder;
+                }
+                k += 1;
+                paths.push(path);
+            }
+            paths
+        })
+        .collect();
+    (levels, tower.top.unwrap_or(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::point;
+
+    use crate::slicer::{
+        material::{
+            insert_wipe_tower, split_by_extruder, split_walls_by_extruder, WipeTowerSettings,
+        },
+        mesh::Mesh,
+        perimeters::inner_walls,
+        slice_path::SlicePath,
+        toolpath::{PathRole, ToolPath},
+        triangle::Triangle,
+    };
+
+    #[test]
+    fn test_split_by_extruder() {
+        let points = vec![
+            point![0.0, 0.0, 0.0],
+            point![0.0, 2.0, 0.0],
+            point![2.0, 2.0, 0.0],
+            point![2.0, 0.0, 0.0],
+            point![0.0, 0.0, 0.0],
+        ];
+        // A wall below the ring, covering its rear edge
+        let mut wall = Triangle::new(
+            point![-1.0, 2.0, -1.0],
+            point![3.0, 2.0, -1.0],
+            point![1.0, 2.0, 1.0],
+        );
+        wall.attributes.extruder = Some(1);
+        let ring = SlicePath {
+            points,
+            closed: true,
+            ..Default::default()
+        };
+
+        let runs = split_by_extruder(ring, Some(&Mesh::from(vec![wall])), 0);
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].0, 1);
+        assert_eq!(
+            runs[0].1.points,
+            vec![point![0.0, 2.0, 0.0], point![2.0, 2.0, 0.0]]
+        );
+        assert_eq!(runs[1].0, 0);
+        assert_eq!(runs[1].1.points.len(), 4);
+    }
+
+    #[test]
+    fn test_split_walls_by_extruder() {
+        let points = vec![
+            point![0.0, 0.0, 0.0],
+            point![0.0, 10.0, 0.0],
+            point![10.0, 10.0, 0.0],
+            point![10.0, 0.0, 0.0],
+            point![0.0, 0.0, 0.0],
+        ];
+        // A wall below the ring, covering its rear edge
+        let mut wall = Triangle::new(
+            point![-5.0, 10.0, -1.0],
+            point![15.0, 10.0, -1.0],
+            point![5.0, 10.0, 1.0],
+        );
+        wall.attributes.extruder = Some(1);
+        let painted = Mesh::from(vec![wall]);
+        let ring = SlicePath {
+            points,
+            closed: true,
+            ..Default::default()
+        };
+
+        // The inner walls are printed with the extruder of the outer wall they follow
+        let inner = inner_walls(std::slice::from_ref(&ring), |_| 3, 1.0).remove(0);
+        let runs = split_walls_by_extruder(inner, &ring, Some(&painted), 0);
+        assert_eq!(runs.len(), 4);
+        assert_eq!(runs[0].0, 1);
+        assert_eq!(
+            runs[0].1.points,
+            vec![point![1.0, 9.0, 0.0], point![9.0, 9.0, 0.0]]
+        );
+        assert_eq!(runs[1].0, 0);
+        assert_eq!(runs[2].0, 1);
+        assert_eq!(
+            runs[2].1.points,
+            vec![point![2.0, 8.0, 0.0], point![8.0, 8.0, 0.0]]
+        );
+        assert_eq!(runs[3].0, 0);
+    }
+
+    #[test]
+    fn test_wipe_tower() {
+        let path = |z: f64, extruder: usize| ToolPath {
+            extruder,
+            ..ToolPath::planar(vec![point![0.0, 0.0, z], point![1.0, 0.0, z]], 1.0, false)
+        };
+        let levels = vec![
+            vec![path(0.2, 0), path(0.2, 1)],
+            vec![path(0.4, 1)],
+            vec![path(0.6, 0)],
+            vec![path(0.8, 0)],
+        ];
+        let settings = WipeTowerSettings {
+            position: point![50.0, 50.0],
+            width: 10.0,
+            depth: 10.0,
+            purge_volume: 5.0,
+            line_width: 0.5,
+            layer_height: 0.2,
+        };
+
+        let (levels, height) = insert_wipe_tower(levels, &settings);
+        let tower = |level: &Vec<ToolPath>| {
+            level
+                .iter()
+                .filter(|path| path.role == PathRole::WipeTower)
+                .map(|path| (path.closed, path.extruder))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tower(&levels[0]), vec![(true, 0), (false, 1)]);
+        assert_eq!(tower(&levels[1]), vec![(true, 1)]);
+        assert_eq!(tower(&levels[2]), vec![(true, 1), (false, 0)]);
+        assert!(tower(&levels[3]).is_empty());
+        assert_eq!(height, 0.6);
+    }
+
+    #[test]
+    fn test_wipe_tower_gaps() {
+        let path = |z: f64, extruder: usize| ToolPath {
+            extruder,
+            ..ToolPath::planar(vec![point![0.0, 0.0, z], point![1.0, 0.0, z]], 1.0, false)
+        };
+        // The planar layers skip from 0.2 to 0.8 over a non-planar surface
+        let levels = vec![vec![path(0.2, 0), path(0.2, 1)], vec![path(0.8, 0)]];
+        let settings = WipeTowerSettings {
+            position: point![50.0, 50.0],
+            width: 10.0,
+            depth: 10.0,
+            purge_volume: 5.0,
+            line_width: 0.5,
+            layer_height: 0.2,
+        };
+
+        let (levels, height) = insert_wipe_tower(levels, &settings);
+        let outlines = levels[1]
+            .iter()
+            .filter(|path| path.role == PathRole::WipeTower && path.closed)
+            .collect::<Vec<_>>();
+        assert_eq!(outlines.len(), 3);
+        for (outline, z) in outlines.iter().zip([0.4, 0.6, 0.8]) {
+            assert_relative_eq!(outline.points[0].z, z, epsilon = 1e-9);
+            assert_relative_eq!(outline.flow[0], 1.0, epsilon = 1e-9);
+        }
+        assert_relative_eq!(height, 0.8, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_wipe_tower_overflow() {
+        let path = |z: f64, extruder: usize| ToolPath {
+            extruder,
+            ..ToolPath::planar(vec![point![0.0, 0.0, z], point![1.0, 0.0, z]], 1.0, false)
+        };
+        let levels = vec![vec![path(0.2, 0), path(0.2, 1)]];
+        // A layer of the tower holds 18 rows of 0.9 mm³, the purge needs 45 of them
+        let settings = WipeTowerSettings {
+            position: point![50.0, 50.0],
+            width: 10.0,
+            depth: 10.0,
+            purge_volume: 40.0,
+            line_width: 0.5,
+            layer_height: 0.2,
+        };
+
+        let (levels, height) = insert_wipe_tower(levels, &settings);
+        let tower = levels[0]
+            .iter()
+            .filter(|path| path.role == PathRole::WipeTower)
+            .collect::<Vec<_>>();
+        let rows = tower
+            .iter()
+            .filter(|path| !path.closed)
+            .map(|path| (path.points.len() / 2, path.points[0].z))
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3);
+        for ((rows, z), (expected, expected_z)) in rows.iter().zip([(18, 0.2), (18, 0.4), (9, 0.6)])
+        {
+            assert_eq!(*rows, expected);
+            assert_relative_eq!(*z, expected_z, epsilon = 1e-9);
+        }
+        assert_eq!(tower.iter().filter(|path| path.closed).count(), 3);
+        assert_relative_eq!(height, 0.6, epsilon = 1e-9);
+    }
+}
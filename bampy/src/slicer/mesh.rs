@@ -1,6 +1,8 @@
 use super::{
     axis::Axis,
-    base_slices::BaseSlice,
+    base_slices::{BaseSlice, ContourSettings, Gap},
+    frame::build_rotation,
+    job::Job,
     line::Line3,
     slice_path::{SlicePath, SurfacePathIterator},
     triangle::Triangle,
@@ -10,6 +12,9 @@ use bvh::{
     aabb::Aabb,
     bvh::{Bvh, BvhNode},
 };
+use nalgebra::{vector, Affine3, Point2, Point3, Rotation3, Translation3};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 #[derive(Debug)]
 pub struct Mesh {
@@ -38,75 +43,201 @@ impl From<Vec<Triangle>> for Mesh {
 }
 
 impl Mesh {
+    /// Transforms the mesh in place and rebuilds the bounding box and hierarchy.
+    /// Mirroring transforms flip the winding, so the normals keep pointing outwards.
+    pub fn transform(&mut self, transform: &Affine3<FloatValue>) {
+        let mirrored = transform
+            .matrix()
+            .fixed_view::<3, 3>(0, 0)
+            .determinant()
+            .is_sign_negative();
+        let triangles = std::mem::take(&mut self.triangles)
+            .into_iter()
+            .map(|triangle| {
+                let (a, b, c) = (
+                    transform * triangle.a,
+                    transform * triangle.b,
+                    transform * triangle.c,
+                );
+                let mut transformed = if mirrored {
+                    Triangle::new(a, c, b)
+                } else {
+                    Triangle::new(a, b, c)
+                };
+                transformed.attributes = triangle.attributes;
+                transformed
+            })
+            .collect::<Vec<_>>();
+        *self = Mesh::from(triangles);
+    }
+
+    /// The rotation that lays a face flat onto the bed,
+    /// if the mesh has that face and it has a normal
+    pub fn lay_flat(&self, face: usize) -> Option<Rotation3<FloatValue>> {
+        build_rotation(&-self.triangles.get(face)?.normal)
+    }
+
+    /// The translation that drops the mesh onto the bed,
+    /// and centres it on a point of the bed if one is given
+    pub fn bed_placement(&self, center: Option<Point2<FloatValue>>) -> Translation3<FloatValue> {
+        let offset = center.map_or(vector![0.0, 0.0], |center| center - self.aabb.center().xy());
+        Translation3::new(offset.x, offset.y, -self.aabb.min.z)
+    }
+
     pub fn slice_paths<'a>(
         self: &'a Mesh,
         axis: Axis,
-        slice_height: FloatValue,
+        layers: Vec<FloatValue>,
+        job: &'a Job,
     ) -> impl Iterator<Item = Vec<SlicePath>> + 'a {
-        self.slice_base_slices(axis, slice_height)
-            .map(|slice| slice.find_paths())
+        self.slice_contours(axis, layers, ContourSettings::default(), job)
+            .map(|(paths, _)| paths)
             .filter(|paths| !paths.is_empty())
     }
 
-    pub fn slice_surface(&self, axis: Axis, nozzle_width: FloatValue) -> SurfacePathIterator {
-        SurfacePathIterator::new(self, axis, nozzle_width)
+    /// The paths of each layer, with the gaps that were found chaining them
+    pub fn slice_contours<'a>(
+        self: &'a Mesh,
+        axis: Axis,
+        layers: Vec<FloatValue>,
+        settings: ContourSettings,
+        job: &'a Job,
+    ) -> impl Iterator<Item = (Vec<SlicePath>, Vec<Gap>)> + 'a {
+        let slices = self
+            .slice_base_slices(axis, layers, job)
+            .collect::<Vec<_>>();
+        crate::maybe_par_iter!(slices)
+            .map(|slice| slice.find_paths_with_gaps(&settings))
+            .collect::<Vec<_>>()
+            .into_iter()
     }
 
-    pub fn slice_base_slices<'a>(
-        self: &'a Mesh,
+    pub fn slice_surface(
+        &self,
         axis: Axis,
-        slice_height: FloatValue,
-    ) -> impl Iterator<Item = BaseSlice> + 'a {
+        nozzle_width: FloatValue,
+        job: &Job,
+    ) -> SurfacePathIterator {
+        SurfacePathIterator::new(self, axis, nozzle_width, job)
+    }
+
+    /// The positions of the layers along the axis, starting at the bottom of the mesh
+    pub fn layer_positions(&self, axis: Axis, slice_height: FloatValue) -> Vec<FloatValue> {
         let layer_count = ((self.aabb.max[axis as usize] - self.aabb.min[axis as usize])
             / slice_height)
             .floor() as usize;
 
-        (0..=layer_count).map(move |i| {
-            let layer = i as FloatValue * slice_height + self.aabb.min[axis as usize];
-            let mut base_slice = BaseSlice {
-                i,
-                d: layer,
-                axis,
-                lines: vec![],
-            };
+        (0..=layer_count)
+            .map(|i| i as FloatValue * slice_height + self.aabb.min[axis as usize])
+            .collect()
+    }
 
-            let mut stack = Vec::<usize>::with_capacity(self.bvh.nodes.len());
-            stack.push(0);
-            while let Some(i) = stack.pop() {
-                match self.bvh.nodes[i] {
-                    BvhNode::Node {
-                        parent_index: _,
-                        child_l_index,
-                        child_l_aabb,
-                        child_r_index,
-                        child_r_aabb,
-                    } => {
-                        assert!(child_l_aabb.min[axis as usize] <= child_l_aabb.max[axis as usize]);
-                        assert!(child_r_aabb.min[axis as usize] <= child_r_aabb.max[axis as usize]);
-                        if layer >= child_l_aabb.min[axis as usize]
-                            && layer <= child_l_aabb.max[axis as usize]
-                        {
-                            stack.push(child_l_index);
+    /// Stops early once the job is cancelled
+    pub fn slice_base_slices<'a>(
+        self: &'a Mesh,
+        axis: Axis,
+        layers: Vec<FloatValue>,
+        job: &'a Job,
+    ) -> impl Iterator<Item = BaseSlice> + 'a {
+        let slices = crate::maybe_par_iter!(layers)
+            .enumerate()
+            .map(|(i, layer)| {
+                if job.is_cancelled() {
+                    return None;
+                }
+                let mut base_slice = BaseSlice {
+                    i,
+                    d: layer,
+                    axis,
+                    lines: vec![],
+                };
+
+                let mut stack = Vec::<usize>::with_capacity(self.bvh.nodes.len());
+                stack.push(0);
+                while let Some(i) = stack.pop() {
+                    match self.bvh.nodes[i] {
+                        BvhNode::Node {
+                            parent_index: _,
+                            child_l_index,
+                            child_l_aabb,
+                            child_r_index,
+                            child_r_aabb,
+                        } => {
+                            assert!(
+                                child_l_aabb.min[axis as usize] <= child_l_aabb.max[axis as usize]
+                            );
+                            assert!(
+                                child_r_aabb.min[axis as usize] <= child_r_aabb.max[axis as usize]
+                            );
+                            if layer >= child_l_aabb.min[axis as usize]
+                                && layer <= child_l_aabb.max[axis as usize]
+                            {
+                                stack.push(child_l_index);
+                            }
+                            if layer >= child_r_aabb.min[axis as usize]
+                                && layer <= child_r_aabb.max[axis as usize]
+                            {
+                                stack.push(child_r_index);
+                            }
                         }
-                        if layer >= child_r_aabb.min[axis as usize]
-                            && layer <= child_r_aabb.max[axis as usize]
-                        {
-                            stack.push(child_r_index);
+                        BvhNode::Leaf {
+                            parent_index: _,
+                            shape_index,
+                        } => {
+                            base_slice.lines.extend(
+                                self.triangles[shape_index].intersect(layer, axis as usize),
+                            );
                         }
                     }
-                    BvhNode::Leaf {
-                        parent_index: _,
-                        shape_index,
-                    } => {
-                        for line in self.triangles[shape_index].intersect(layer, axis as usize) {
-                            base_slice.lines.push(line);
-                        }
+                }
+
+                Some(base_slice)
+            });
+        slices
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map_while(|slice| slice)
+    }
+
+    /// A triangle the point lies on, within a distance of `tolerance`
+    pub fn triangle_at(
+        &self,
+        point: &Point3<FloatValue>,
+        tolerance: FloatValue,
+    ) -> Option<&Triangle> {
+        if self.bvh.nodes.is_empty() {
+            return None;
+        }
+        let mut stack = Vec::<usize>::new();
+        stack.push(0);
+        while let Some(i) = stack.pop() {
+            match self.bvh.nodes[i] {
+                BvhNode::Node {
+                    parent_index: _,
+                    child_l_index,
+                    child_l_aabb,
+                    child_r_index,
+                    child_r_aabb,
+                } => {
+                    if child_l_aabb.approx_contains_eps(point, tolerance) {
+                        stack.push(child_l_index);
+                    }
+                    if child_r_aabb.approx_contains_eps(point, tolerance) {
+                        stack.push(child_r_index);
+                    }
+                }
+                BvhNode::Leaf {
+                    parent_index: _,
+                    shape_index,
+                } => {
+                    let triangle = &self.triangles[shape_index];
+                    if triangle.touches(point, tolerance) {
+                        return Some(triangle);
                     }
                 }
             }
-
-            base_slice
-        })
+        }
+        None
     }
 
     pub fn outline_base_slice(&self, axis: Axis) -> BaseSlice {
@@ -184,3 +315,44 @@ impl Mesh {
         base_slice
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{point, vector, Affine3, Matrix4};
+
+    use crate::slicer::{mesh::Mesh, triangle::Triangle};
+
+    #[test]
+    fn test_transform_placement() {
+        let mut mesh = Mesh::from(vec![Triangle::new(
+            point![1.0, 1.0, 2.0],
+            point![3.0, 1.0, 2.0],
+            point![1.0, 3.0, 4.0],
+        )]);
+        let normal = mesh.triangles[0].normal;
+
+        let mirror = Affine3::from_matrix_unchecked(Matrix4::new_nonuniform_scaling(&vector![
+            -1.0, 1.0, 1.0
+        ]));
+        mesh.transform(&mirror);
+        assert_relative_eq!(
+            mesh.triangles[0].normal,
+            vector![-normal.x, normal.y, normal.z]
+        );
+        assert_relative_eq!(mesh.aabb.min, point![-3.0, 1.0, 2.0]);
+
+        mesh.transform(&nalgebra::convert(mesh.lay_flat(0).unwrap()));
+        assert_relative_eq!(
+            mesh.triangles[0].normal,
+            vector![0.0, 0.0, -1.0],
+            epsilon = 1e-9
+        );
+
+        mesh.transform(&nalgebra::convert(
+            mesh.bed_placement(Some(point![10.0, 10.0])),
+        ));
+        assert_relative_eq!(mesh.aabb.min.z, 0.0, epsilon = 1e-9);
+        assert_relative_eq!(mesh.aabb.center().xy(), point![10.0, 10.0], epsilon = 1e-9);
+    }
+}
@@ -2,14 +2,34 @@ use bvh::aabb::{Aabb, Bounded};
 use bvh::bounding_hierarchy::BHValue;
 use nalgebra::Point;
 
+pub mod adhesion;
+pub mod arc;
 pub mod axis;
 pub mod base_slices;
+pub mod bridge;
+pub mod extrusion;
+pub mod frame;
+pub mod job;
+pub mod layer_heights;
 pub mod line;
+pub mod material;
 pub mod mesh;
+pub mod modifier;
+pub mod orientation;
+pub mod paint;
+pub mod path_order;
+pub mod perimeters;
+pub mod plate;
+pub mod polygon;
 pub mod sdf;
+pub mod seam;
+pub mod simplify;
 pub mod slice_path;
 pub mod split_surface;
+pub mod support;
+pub mod toolpath;
 pub mod trace_surface;
+pub mod tree_support;
 pub mod triangle;
 pub mod z_projection;
 
@@ -0,0 +1,310 @@
+use nalgebra::Point3;
+
+use super::{
+    mesh::Mesh, sdf::Sdf, slice_path::SlicePath, support::vertical_hits, toolpath::ToolPath,
+    triangle::Triangle, FloatValue,
+};
+
+/// The region of a modifier
+pub enum Volume {
+    /// A closed mesh, where points below an odd number of its triangles are inside
+    Mesh(Mesh),
+    /// Points where the distance is negative are inside
+    Sdf(Box<dyn Sdf<3> + Send + Sync>),
+}
+
+impl Volume {
+    pub fn contains(&self, point: &Point3<FloatValue>) -> bool {
+        match self {
+            Volume::Mesh(mesh) => {
+                let aabb = &mesh.aabb;
+                if point.x < aabb.min.x
+                    || point.x > aabb.max.x
+                    || point.y < aabb.min.y
+                    || point.y > aabb.max.y
+                    || point.z < aabb.min.z
+                    || point.z > aabb.max.z
+                {
+                    return false;
+                }
+                vertical_hits(mesh, point.x, point.y)
+                    .iter()
+                    .filter(|(z, _)| *z > point.z)
+                    .count()
+                    % 2
+                    == 1
+            }
+            Volume::Sdf(sdf) => sdf.sdf(point) <= 0.0,
+        }
+    }
+}
+
+/// Settings that override the ones of the object, `None` keeps them
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ModifierSettings {
+    /// Forces surfaces to be printed non-planar or planar, regardless of their angle
+    pub non_planar: Option<bool>,
+    /// Extrusion multiplier on top of the flow of the path
+    pub flow: Option<FloatValue>,
+    pub perimeters: Option<usize>,
+}
+
+/// Settings overridden inside a volume.
+/// Where modifiers overlap, the later one wins.
+pub struct Modifier {
+    pub volume: Volume,
+    pub settings: ModifierSettings,
+}
+
+/// The setting of the last modifier at the point that overrides it
+fn setting<T, F>(modifiers: &[Modifier], point: &Point3<FloatValue>, get: F) -> Option<T>
+where
+    F: Fn(&ModifierSettings) -> Option<T>,
+{
+    modifiers
+        .iter()
+        .rev()
+        .filter_map(|modifier| get(&modifier.settings).map(|value| (modifier, value)))
+        .find(|(modifier, _)| modifier.volume.contains(point))
+        .map(|(_, value)| value)
+}
+
+/// Whether a modifier forces a triangle into or out of the non-planar surfaces,
+/// decided at its centre
+pub fn non_planar_override(modifiers: &[Modifier], triangle: &Triangle) -> Option<bool> {
+    let centre = Point3::from((triangle.a.coords + triangle.b.coords + triangle.c.coords) / 3.0);
+    setting(modifiers, &centre, |settings| settings.non_planar)
+}
+
+/// The number of pieces a segment is split into, so none is longer than `resolution`
+fn pieces(length: FloatValue, resolution: FloatValue) -> usize {
+    if resolution > 0.0 {
+        (length / resolution).ceil().max(1.0) as usize
+    } else {
+        1
+    }
+}
+
+/// The number of perimeters of a ring, taken from the setting that covers most of its length,
+/// measured at the resolution of the extrusion width
+pub fn ring_perimeters(
+    modifiers: &[Modifier],
+    ring: &SlicePath,
+    perimeters: usize,
+    width: FloatValue,
+) -> usize {
+    if modifiers.is_empty() {
+        return perimeters;
+    }
+    let mut lengths = Vec::<(usize, FloatValue)>::new();
+    for segment in ring.points.windows(2) {
+        let delta = segment[1] - segment[0];
+        let pieces = pieces(delta.norm(), width);
+        for piece in 0..pieces {
+            let middle = segment[0] + delta * (piece as FloatValue + 0.5) / pieces as FloatValue;
+            let count =
+                setting(modifiers, &middle, |settings| settings.perimeters).unwrap_or(perimeters);
+            let length = delta.norm() / pieces as FloatValue;
+            match lengths.iter_mut().find(|(other, _)| *other == count) {
+                Some((_, total)) => *total += length,
+                None => lengths.push((count, length)),
+            }
+        }
+    }
+    lengths
+        .into_iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(perimeters, |(count, _)| count)
+}
+
+/// Scales the flow of the path where it runs through a modifier.
+/// Segments are split where the flow changes, at the resolution of the extrusion width,
+/// so long lines like those of bridges and support pick up modifiers along the way.
+pub fn modify_flow(modifiers: &[Modifier], path: ToolPath) -> ToolPath {
+    if modifiers.is_empty() {
+        return path;
+    }
+    let mut points = Vec::with_capacity(path.points.len());
+    let mut flows = Vec::with_capacity(path.flow.len());
+    points.extend(path.points.first().copied());
+    for (segment, flow) in path.points.windows(2).zip(path.flow.iter()) {
+        let delta = segment[1] - segment[0];
+        let pieces = pieces(delta.norm(), path.width);
+        let mut scale = None;
+        for piece in 0..pieces {
+            let start = piece as FloatValue / pieces as FloatValue;
+            let middle = segment[0] + delta * (piece as FloatValue + 0.5) / pieces as FloatValue;
+            let piece_scale = setting(modifiers, &middle, |settings| settings.flow).unwrap_or(1.0);
+            match scale {
+                Some(scale) if scale != piece_scale => {
+                    points.push(segment[0] + delta * start);
+                    flows.push(flow * scale);
+                }
+                _ => {}
+            }
+            scale = Some(piece_scale);
+        }
+        points.push(segment[1]);
+        flows.push(flow * scale.unwrap_or(1.0));
+    }
+    ToolPath {
+        points,
+        flow: flows,
+        ..path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{point, Point3};
+
+    use crate::slicer::{
+        aabb_from_points,
+        axis::Axis,
+        mesh::Mesh,
+        modifier::{modify_flow, ring_perimeters, Modifier, ModifierSettings, Volume},
+        sdf::{Sdf3dModifiers, SdfSphere},
+        slice_path::SlicePath,
+        toolpath::ToolPath,
+        triangle::Triangle,
+    };
+
+    /// The closed triangles of an axis aligned box, facing outwards
+    fn cuboid(min: Point3<f64>, max: Point3<f64>) -> Mesh {
+        let c = |x: usize, y: usize, z: usize| {
+            point![[min.x, max.x][x], [min.y, max.y][y], [min.z, max.z][z]]
+        };
+        Mesh::from(
+            [
+                [c(0, 0, 0), c(0, 1, 0), c(1, 1, 0), c(1, 0, 0)],
+                [c(0, 0, 1), c(1, 0, 1), c(1, 1, 1), c(0, 1, 1)],
+                [c(0, 0, 0), c(1, 0, 0), c(1, 0, 1), c(0, 0, 1)],
+                [c(0, 1, 0), c(0, 1, 1), c(1, 1, 1), c(1, 1, 0)],
+                [c(0, 0, 0), c(0, 0, 1), c(0, 1, 1), c(0, 1, 0)],
+                [c(1, 0, 0), c(1, 1, 0), c(1, 1, 1), c(1, 0, 1)],
+            ]
+            .iter()
+            .flat_map(|[a, b, c, d]| [Triangle::new(*a, *b, *c), Triangle::new(*a, *c, *d)])
+            .collect::<Vec<_>>(),
+        )
+    }
+
+    fn perimeters(volume: Volume, perimeters: usize) -> Modifier {
+        Modifier {
+            volume,
+            settings: ModifierSettings {
+                perimeters: Some(perimeters),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// A clockwise square ring at height 1
+    fn square(min: f64, max: f64) -> SlicePath {
+        let points = vec![
+            point![min, min, 1.0],
+            point![min, max, 1.0],
+            point![max, max, 1.0],
+            point![max, min, 1.0],
+            point![min, min, 1.0],
+        ];
+        SlicePath {
+            axis: Axis::Z,
+            closed: true,
+            aabb: aabb_from_points(points.iter()),
+            points,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_mesh_contains() {
+        let volume = Volume::Mesh(cuboid(point![0.0, 0.0, 0.0], point![2.0, 3.0, 4.0]));
+        assert!(volume.contains(&point![1.0, 1.5, 2.0]));
+        assert!(volume.contains(&point![0.1, 2.9, 3.9]));
+        assert!(!volume.contains(&point![1.0, 1.5, 4.5]));
+        assert!(!volume.contains(&point![1.0, 1.5, -0.5]));
+        assert!(!volume.contains(&point![2.5, 1.5, 2.0]));
+    }
+
+    #[test]
+    fn test_ring_perimeters() {
+        let box_around = |min: [f64; 2], max: [f64; 2], count: usize| {
+            perimeters(
+                Volume::Mesh(cuboid(
+                    point![min[0], min[1], 0.0],
+                    point![max[0], max[1], 2.0],
+                )),
+                count,
+            )
+        };
+        let ring = square(0.0, 10.0);
+        // A modifier around one corner doesn't change the ring
+        let corner = [box_around([-1.0, -1.0], [1.0, 1.0], 5)];
+        assert_eq!(ring_perimeters(&corner, &ring, 2, 0.5), 2);
+        // Nor does one around less than half of it
+        let less = [box_around([-1.0, -1.0], [11.0, 4.0], 5)];
+        assert_eq!(ring_perimeters(&less, &ring, 2, 0.5), 2);
+        // One around more than half of it does
+        let more = [box_around([-1.0, -1.0], [11.0, 6.0], 5)];
+        assert_eq!(ring_perimeters(&more, &ring, 2, 0.5), 5);
+        // Where modifiers overlap, the later one wins
+        let all = [
+            box_around([-1.0, -1.0], [11.0, 11.0], 5),
+            box_around([-2.0, -2.0], [12.0, 12.0], 1),
+        ];
+        assert_eq!(ring_perimeters(&all, &ring, 2, 0.5), 1);
+    }
+
+    #[test]
+    fn test_modify_bridge_flow() {
+        // A single long bridge line running through a modifier
+        let modifiers = [Modifier {
+            volume: Volume::Sdf(Box::new(
+                SdfSphere::new(1.0).translate(point![5.0, 0.0, 0.0]),
+            )),
+            settings: ModifierSettings {
+                flow: Some(2.0),
+                ..Default::default()
+            },
+        }];
+        let path = ToolPath {
+            width: 0.5,
+            ..ToolPath::planar(
+                vec![point![0.0, 0.0, 0.0], point![10.0, 0.0, 0.0]],
+                1.0,
+                false,
+            )
+        };
+        let path = modify_flow(&modifiers, path);
+        assert_eq!(path.flow, vec![1.0, 2.0, 1.0]);
+        assert_eq!(
+            path.points,
+            vec![
+                point![0.0, 0.0, 0.0],
+                point![4.0, 0.0, 0.0],
+                point![6.0, 0.0, 0.0],
+                point![10.0, 0.0, 0.0]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_modify_flow() {
+        let sphere = |x: f64, flow: f64| Modifier {
+            volume: Volume::Sdf(Box::new(SdfSphere::new(1.0).translate(point![x, 0.0, 0.0]))),
+            settings: ModifierSettings {
+                flow: Some(flow),
+                ..Default::default()
+            },
+        };
+        let modifiers = [sphere(0.0, 2.0), sphere(1.0, 0.5)];
+        let path = ToolPath::planar(
+            (0..5).map(|x| point![x as f64 - 2.0, 0.0, 0.0]).collect(),
+            1.0,
+            false,
+        );
+        // Segments centred at -1.5, -0.5, 0.5 and 1.5, the later modifier wins
+        assert_eq!(modify_flow(&modifiers, path).flow, vec![1.0, 2.0, 0.5, 0.5]);
+    }
+}
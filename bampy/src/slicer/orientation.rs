@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use nalgebra::{vector, UnitQuaternion, Vector3};
+
+use super::{
+    frame::{build_rotation, BuildFrame},
+    job::{Cancelled, Job},
+    split_surface::{has_min_area, is_top_surface, split_surface, SplitSettings},
+    support::is_overhang,
+    triangle::Triangle,
+    FloatValue,
+};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Number of the largest flat faces tried as the bottom of the model
+const FLAT_FACES: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct OrientationSettings {
+    pub max_angle: FloatValue,
+    /// Surfaces smaller than this are printed planar and don't count
+    pub min_surface_area: FloatValue,
+    pub max_overhang_angle: FloatValue,
+    /// How much an area of overhang costs compared to the same area of non-planar surface
+    pub overhang_weight: FloatValue,
+    /// Triangles this close to the lowest point rest on the bed and don't overhang
+    pub bed_tolerance: FloatValue,
+    pub split: SplitSettings,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OrientationScore {
+    pub build_direction: Vector3<FloatValue>,
+    /// Rotation of the model onto the bed
+    pub rotation: UnitQuaternion<FloatValue>,
+    /// Area of the non-planar surfaces that are large enough to be printed
+    pub surface_area: FloatValue,
+    /// Area facing down past the overhang angle, not counting the bottom on the bed
+    pub overhang_area: FloatValue,
+    pub score: FloatValue,
+}
+
+/// Evenly spread directions on the unit sphere, along a Fibonacci spiral
+pub fn sphere_directions(count: usize) -> Vec<Vector3<FloatValue>> {
+    let golden_angle = std::f64::consts::PI * (3.0 - FloatValue::sqrt(5.0));
+    (0..count)
+        .map(|i| {
+            let z = 1.0 - 2.0 * (i as FloatValue + 0.5) / count as FloatValue;
+            let radius = (1.0 - z * z).sqrt();
+            let angle = golden_angle * i as FloatValue;
+            vector![radius * angle.cos(), radius * angle.sin(), z]
+        })
+        .collect()
+}
+
+/// Directions that lay the largest flat faces onto the bed.
+/// Sampled directions rarely hit these exactly, and a slight tilt turns a face on the bed
+/// into an overhang.
+pub fn flat_face_directions(triangles: &[Triangle], count: usize) -> Vec<Vector3<FloatValue>> {
+    let mut faces = HashMap::<[i64; 3], (Vector3<FloatValue>, FloatValue)>::new();
+    for triangle in triangles {
+        let key = [0, 1, 2].map(|axis| (triangle.normal[axis] * 1e4).round() as i64);
+        let face = faces.entry(key).or_insert((triangle.normal, 0.0));
+        face.1 += triangle.area();
+    }
+    let mut faces = faces.into_values().collect::<Vec<_>>();
+    faces.sort_by(|a, b| b.1.total_cmp(&a.1));
+    faces
+        .into_iter()
+        .take(count)
+        .map(|(normal, _)| -normal)
+        .collect()
+}
+
+/// Scores the model printed towards a build direction,
+/// or nothing if the direction has no length like the normal of a degenerate face.
+///
+/// The triangles are classified the same way as when slicing, so the surface area is what
+/// would end up printed non-planar.
+pub fn score_orientation(
+    triangles: &[Triangle],
+    build_direction: &Vector3<FloatValue>,
+    settings: &OrientationSettings,
+    job: &Job,
+) -> Result<Option<OrientationScore>, Cancelled> {
+    let (Some(rotation), Some(frame)) = (
+        build_rotation(build_direction),
+        BuildFrame::new(build_direction),
+    ) else {
+        return Ok(None);
+    };
+    let triangles = triangles
+        .iter()
+        .map(|triangle| {
+            Triangle::new(
+                frame.to_bed(&triangle.a),
+                frame.to_bed(&triangle.b),
+                frame.to_bed(&triangle.c),
+            )
+        })
+        .collect::<Vec<_>>();
+    let bed = triangles
+        .iter()
+        .map(|triangle| triangle.aabb.min.z)
+        .fold(FloatValue::MAX, FloatValue::min);
+
+    let mut surface_triangles = Vec::new();
+    let mut overhang_area = 0.0;
+    for triangle in triangles {
+        if is_overhang(&triangle, &Vector3::z(), settings.max_overhang_angle) {
+            if triangle.aabb.max.z > bed + settings.bed_tolerance {
+                overhang_area += triangle.area();
+            }
+        } else if is_top_surface(&triangle, &Vector3::z(), settings.max_angle) {
+            surface_triangles.push(triangle);
+        }
+    }
+    let surface_area = split_surface(surface_triangles, &settings.split, job)?
+        .into_iter()
+        .filter(|mesh| has_min_area(mesh, settings.min_surface_area))
+        .map(|mesh| {
+            mesh.triangles
+                .iter()
+                .map(Triangle::area)
+                .sum::<FloatValue>()
+        })
+        .sum::<FloatValue>();
+
+    Ok(Some(OrientationScore {
+        build_direction: build_direction.normalize(),
+        rotation: UnitQuaternion::from_rotation_matrix(&rotation),
+        surface_area,
+        overhang_area,
+        score: surface_area - settings.overhang_weight * overhang_area,
+    }))
+}
+
+/// Scores build directions sampled over the sphere, as well as those laying the largest faces
+/// flat, and returns the best, highest score first
+pub fn optimise_orientation(
+    triangles: &[Triangle],
+    samples: usize,
+    results: usize,
+    settings: &OrientationSettings,
+    job: &Job,
+) -> Result<Vec<OrientationScore>, Cancelled> {
+    let mut directions = sphere_directions(samples);
+    directions.extend(flat_face_directions(triangles, FLAT_FACES));
+    let mut scores = crate::maybe_par_iter!(directions)
+        .map(|direction| score_orientation(triangles, &direction, settings, job))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    scores.sort_by(|a, b| b.score.total_cmp(&a.score));
+    scores.truncate(results);
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{point, vector};
+
+    use crate::slicer::{
+        job::Job,
+        orientation::{optimise_orientation, OrientationSettings},
+        triangle::Triangle,
+    };
+
+    #[test]
+    fn test_flat_plate() {
+        // A thin plate with its large faces along the X axis
+        let (x, y, z) = (0.5, 10.0, 10.0);
+        let corner = |i: usize| {
+            point![
+                if i & 1 == 0 { 0.0 } else { x },
+                if i & 2 == 0 { 0.0 } else { y },
+                if i & 4 == 0 { 0.0 } else { z }
+            ]
+        };
+        let faces = [
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+        ];
+        let triangles = faces
+            .iter()
+            .flat_map(|[a, b, c, d]| {
+                [
+                    Triangle::new(corner(*a), corner(*b), corner(*c)),
+                    Triangle::new(corner(*a), corner(*c), corner(*d)),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let settings = OrientationSettings {
+            max_angle: 0.3,
+            min_surface_area: 1.0,
+            max_overhang_angle: std::f64::consts::FRAC_PI_4,
+            overhang_weight: 1.0,
+            bed_tolerance: 0.1,
+            split: Default::default(),
+        };
+        let best = optimise_orientation(&triangles, 64, 1, &settings, &Job::new()).unwrap();
+        assert_eq!(best.len(), 1);
+        // Lying flat on one of its large faces
+        assert!(best[0].build_direction.dot(&vector![1.0, 0.0, 0.0]).abs() > 0.95);
+        assert!((best[0].surface_area - y * z).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,100 @@
+//! Per-triangle paint in the format PrusaSlicer and Bambu Studio store in 3MF files.
+//!
+//! Each triangle carries a hex string encoding a tree of subdivisions, read from the last
+//! character to the first. The low two bits of a nibble are the number of split sides,
+//! leaves store their state in the high two bits, with `0b11` followed by another nibble
+//! holding states from 3 upwards.
+
+use super::FloatValue;
+
+/// Distance within which a point of a path lies on a painted triangle
+pub const PAINT_TOLERANCE: FloatValue = 1e-6;
+
+/// Seam, support and non-planar paint, stored as 0, 1 and 2 in 3MF files
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Paint {
+    #[default]
+    None,
+    Enforcer,
+    Blocker,
+}
+
+impl From<usize> for Paint {
+    fn from(state: usize) -> Self {
+        match state {
+            1 => Paint::Enforcer,
+            2 => Paint::Blocker,
+            _ => Paint::None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TriangleAttributes {
+    pub seam: Paint,
+    pub support: Paint,
+    pub non_planar: Paint,
+    /// Index of the extruder painted onto the triangle, `None` keeps the extruder of the object
+    pub extruder: Option<usize>,
+}
+
+fn decode_node(
+    nibbles: &mut impl Iterator<Item = usize>,
+    weight: FloatValue,
+    areas: &mut Vec<FloatValue>,
+) -> Option<()> {
+    let code = nibbles.next()?;
+    let split_sides = code & 0b11;
+    if split_sides == 0 {
+        let state = match code >> 2 {
+            0b11 => nibbles.next()? + 3,
+            state => state,
+        };
+        if areas.len() <= state {
+            areas.resize(state + 1, 0.0);
+        }
+        areas[state] += weight;
+    } else {
+        // The sizes of the children depend on the split, sharing the area evenly is close enough
+        let children = split_sides + 1;
+        for _ in 0..children {
+            decode_node(nibbles, weight / children as FloatValue, areas)?;
+        }
+    }
+    Some(())
+}
+
+/// Decodes the paint of a single triangle into the state painted over most of its area.
+/// Empty or malformed data is unpainted.
+pub fn decode_paint(data: &str) -> usize {
+    let mut nibbles = data
+        .chars()
+        .rev()
+        .map_while(|digit| digit.to_digit(16).map(|digit| digit as usize));
+    let mut areas = Vec::new();
+    if decode_node(&mut nibbles, 1.0, &mut areas).is_none() {
+        return 0;
+    }
+    areas
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .map(|(state, _)| state)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slicer::paint::{decode_paint, Paint};
+
+    #[test]
+    fn test_decode_paint() {
+        assert_eq!(decode_paint(""), 0);
+        assert_eq!(Paint::from(decode_paint("4")), Paint::Enforcer);
+        assert_eq!(Paint::from(decode_paint("8")), Paint::Blocker);
+        // Extruder 5, stored as 0b11 and 5 - 3
+        assert_eq!(decode_paint("2C"), 5);
+        // Split along three sides, with two of the four children painted as blockers and one as an enforcer
+        assert_eq!(decode_paint("88043"), 2);
+    }
+}
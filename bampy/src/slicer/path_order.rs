@@ -0,0 +1,249 @@
+use nalgebra::Point3;
+
+use super::{
+    toolpath::{PathRole, ToolPath},
+    FloatValue,
+};
+
+/// Upper bound of 2-opt passes over a single level
+const MAX_PASSES: usize = 16;
+
+/// The points a path is entered and left at, optionally reversed
+fn ends(path: &ToolPath, reversed: bool) -> (Point3<FloatValue>, Point3<FloatValue>) {
+    let first = *path.points.first().unwrap();
+    let last = *path.points.last().unwrap();
+    if path.closed {
+        (first, first)
+    } else if reversed {
+        (last, first)
+    } else {
+        (first, last)
+    }
+}
+
+/// The points a unit is entered and left at, only a single open path can be reversed
+fn unit_ends(unit: &[ToolPath], reversed: bool) -> (Point3<FloatValue>, Point3<FloatValue>) {
+    match unit {
+        [path] => ends(path, reversed),
+        _ => (ends(&unit[0], false).0, ends(unit.last().unwrap(), false).1),
+    }
+}
+
+fn reversible(unit: &[ToolPath]) -> bool {
+    matches!(unit, [path] if !path.closed)
+}
+
+/// Whether reversing a run of units keeps the travel within this unit.
+/// Units that start and end at different points, but can't be reversed, don't.
+fn symmetric(unit: &[ToolPath]) -> bool {
+    let (entry, exit) = unit_ends(unit, false);
+    reversible(unit) || entry == exit
+}
+
+/// Splits a level into the units that are reordered.
+/// The inner walls of a ring are printed from the inside out before its outer wall,
+/// so they stay together with the outer wall that follows them.
+fn units(paths: Vec<ToolPath>) -> Vec<Vec<ToolPath>> {
+    let mut units = Vec::<Vec<ToolPath>>::new();
+    for path in paths {
+        let joined = units
+            .last()
+            .and_then(|unit| unit.last())
+            .is_some_and(|last| {
+                last.role == PathRole::InnerWall
+                    && matches!(path.role, PathRole::InnerWall | PathRole::OuterWall)
+            });
+        match units.last_mut() {
+            Some(unit) if joined => unit.push(path),
+            _ => units.push(vec![path]),
+        }
+    }
+    units
+}
+
+/// The total distance travelled between the end of each path and the start of the next
+pub fn travel_distance<'a, I>(paths: I) -> FloatValue
+where
+    I: IntoIterator<Item = &'a ToolPath>,
+{
+    let mut position: Option<Point3<FloatValue>> = None;
+    let mut distance = 0.0;
+    for path in paths {
+        if path.points.is_empty() {
+            continue;
+        }
+        let (entry, exit) = ends(path, false);
+        if let Some(position) = position {
+            distance += (entry - position).norm();
+        }
+        position = Some(exit);
+    }
+    distance
+}
+
+/// Orders the paths of each level to reduce travel, reversing open paths where it helps.
+///
+/// Levels are printed in the given order, so paths never move across dependency constraints.
+/// Within a level, the walls of a ring keep their order from the inside out.
+pub fn optimise_order(levels: Vec<Vec<ToolPath>>) -> Vec<Vec<ToolPath>> {
+    let mut position: Option<Point3<FloatValue>> = None;
+    levels
+        .into_iter()
+        .map(|level| {
+            let level = optimise_level(level, position);
+            if let Some(last) = level.last() {
+                position = Some(ends(last, false).1);
+            }
+            level
+        })
+        .collect()
+}
+
+fn optimise_level(paths: Vec<ToolPath>, start: Option<Point3<FloatValue>>) -> Vec<ToolPath> {
+    let mut remaining = units(
+        paths
+            .into_iter()
+            .filter(|path| !path.points.is_empty())
+            .collect(),
+    );
+    if remaining.len() < 2 {
+        return remaining.into_iter().flatten().collect();
+    }
+
+    // Nearest neighbour
+    let mut order = Vec::<(Vec<ToolPath>, bool)>::with_capacity(remaining.len());
+    let mut position = start.unwrap_or_else(|| unit_ends(&remaining[0], false).0);
+    while !remaining.is_empty() {
+        let mut best = (0, false, FloatValue::MAX);
+        for (i, unit) in remaining.iter().enumerate() {
+            for reversed in [false, true].iter().copied() {
+                if reversed && !reversible(unit) {
+                    continue;
+                }
+                let distance = (unit_ends(unit, reversed).0 - position).norm();
+                if distance < best.2 {
+                    best = (i, reversed, distance);
+                }
+            }
+        }
+        let unit = remaining.swap_remove(best.0);
+        position = unit_ends(&unit, best.1).1;
+        order.push((unit, best.1));
+    }
+
+    // 2-opt, where reversing a run of units also reverses each open path in it
+    let entry = |order: &[(Vec<ToolPath>, bool)], i: usize| unit_ends(&order[i].0, order[i].1).0;
+    let exit = |order: &[(Vec<ToolPath>, bool)], i: usize| unit_ends(&order[i].0, order[i].1).1;
+    let flipped = |order: &[(Vec<ToolPath>, bool)], i: usize| {
+        let (unit, reversed) = &order[i];
+        unit_ends(unit, *reversed != reversible(unit))
+    };
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+        for i in 0..order.len() {
+            if !symmetric(&order[i].0) {
+                continue;
+            }
+            for j in i + 1..order.len() {
+                if !symmetric(&order[j].0) {
+                    break;
+                }
+                let before = if i == 0 {
+                    start
+                } else {
+                    Some(exit(&order, i - 1))
+                };
+                let after = order.get(j + 1).map(|_| entry(&order, j + 1));
+
+                let mut delta = 0.0;
+                if let Some(before) = before {
+                    delta +=
+                        (flipped(&order, j).0 - before).norm() - (entry(&order, i) - before).norm();
+                }
+                if let Some(after) = after {
+                    delta +=
+                        (after - flipped(&order, i).1).norm() - (after - exit(&order, j)).norm();
+                }
+
+                if delta < -FloatValue::EPSILON {
+                    order[i..=j].reverse();
+                    for (unit, reversed) in &mut order[i..=j] {
+                        *reversed = !*reversed && reversible(unit);
+                    }
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+
+    order
+        .into_iter()
+        .flat_map(|(mut unit, reversed)| {
+            if reversed {
+                unit[0].reverse();
+            }
+            unit
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::point;
+
+    use crate::slicer::{
+        path_order::{optimise_order, travel_distance},
+        toolpath::{PathRole, ToolPath},
+    };
+
+    #[test]
+    fn test_optimise_order() {
+        let line =
+            |x: f64| ToolPath::planar(vec![point![x, 0.0, 0.0], point![x, 1.0, 0.0]], 1.0, false);
+        let level = vec![line(0.0), line(3.0), line(1.0), line(2.0)];
+        let before = travel_distance(&level);
+
+        let optimised = optimise_order(vec![level]).remove(0);
+
+        assert_eq!(optimised.len(), 4);
+        assert!(travel_distance(&optimised) < before);
+        assert!((travel_distance(&optimised) - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_keeps_levels() {
+        let point_path =
+            |x: f64| ToolPath::planar(vec![point![x, 0.0, 0.0], point![x, 0.0, 1.0]], 1.0, false);
+        let optimised = optimise_order(vec![vec![point_path(5.0)], vec![point_path(0.0)]]);
+
+        assert_eq!(optimised[0][0].points[0].x, 5.0);
+        assert_eq!(optimised[1][0].points[0].x, 0.0);
+    }
+
+    #[test]
+    fn test_keeps_wall_order() {
+        let wall = |role: PathRole, x: f64, y: f64| ToolPath {
+            role,
+            ..ToolPath::planar(vec![point![x, y, 0.0], point![x, y + 1.0, 0.0]], 1.0, false)
+        };
+        // Each inner wall ends right next to the outer wall of the other ring
+        let level = vec![
+            wall(PathRole::InnerWall, 0.0, 0.0),
+            wall(PathRole::OuterWall, 5.0, 0.0),
+            wall(PathRole::InnerWall, 5.0, 1.0),
+            wall(PathRole::OuterWall, 0.0, 1.0),
+        ];
+
+        let optimised = optimise_order(vec![level]).remove(0);
+
+        assert_eq!(optimised.len(), 4);
+        for pair in optimised.chunks(2) {
+            assert_eq!(pair[0].role, PathRole::InnerWall);
+            assert_eq!(pair[1].role, PathRole::OuterWall);
+            assert_ne!(pair[0].points[0].x, pair[1].points[0].x);
+        }
+    }
+}
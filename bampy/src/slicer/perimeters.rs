@@ -0,0 +1,178 @@
+use nalgebra::{point, Point2};
+
+use super::{
+    aabb_from_points,
+    polygon::{offset, polygons_within},
+    slice_path::SlicePath,
+    FloatValue,
+};
+
+/// Walls exactly a line width apart don't overlap, even after rounding
+const OVERLAP_TOLERANCE: FloatValue = 1e-9;
+
+/// The points of a closed ring projected onto its layer, without repeated points.
+/// Inner walls have a point for each of them, so the segments of an inner wall
+/// lie across from the segments of the outline they were offset from.
+pub fn outline(ring: &SlicePath) -> Vec<Point2<FloatValue>> {
+    let mut polygon = ring
+        .points
+        .iter()
+        .map(|point| point.xy())
+        .collect::<Vec<Point2<FloatValue>>>();
+    polygon.dedup_by(|a, b| (*a - *b).norm() < FloatValue::EPSILON);
+    while polygon.len() > 1
+        && (polygon[0] - polygon[polygon.len() - 1]).norm() < FloatValue::EPSILON
+    {
+        polygon.pop();
+    }
+    polygon
+}
+
+/// Inner walls of the closed rings of a layer, one less than the perimeters of each ring,
+/// each a line width further into the material.
+///
+/// Holes are the rings inside an odd number of other rings, their walls grow outwards.
+/// Rings that collapse while shrinking are dropped along with all rings further in.
+/// Walls are placed a perimeter at a time, and a ring stops once its next wall would
+/// come closer than a line width to a wall of another ring, like where the walls of
+/// a contour meet those of a hole inside it.
+pub fn inner_walls<F>(
+    rings: &[SlicePath],
+    perimeters: F,
+    line_width: FloatValue,
+) -> Vec<Vec<SlicePath>>
+where
+    F: Fn(&SlicePath) -> usize,
+{
+    // The outline and walls of each ring, which the walls of the others keep clear of
+    let mut placed = rings
+        .iter()
+        .map(|ring| vec![outline(ring)])
+        .collect::<Vec<_>>();
+    let mut growing = rings
+        .iter()
+        .zip(&placed)
+        .map(|(ring, polygons)| {
+            let first = ring.points.first()?;
+            let hole = rings
+                .iter()
+                .filter(|other| !std::ptr::eq(*other, ring) && other.contains(first))
+                .count()
+                % 2
+                == 1;
+            (polygons[0].len() >= 3).then(|| (hole, perimeters(ring)))
+        })
+        .collect::<Vec<_>>();
+    let mut walls = rings.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+    let max_perimeters = growing
+        .iter()
+        .flatten()
+        .map(|(_, perimeters)| *perimeters)
+        .max()
+        .unwrap_or(0);
+    for perimeter in 1..max_perimeters {
+        for (k, ring) in rings.iter().enumerate() {
+            let Some((hole, perimeters)) = growing[k] else {
+                continue;
+            };
+            if perimeter >= perimeters {
+                growing[k] = None;
+                continue;
+            }
+            let polygon = &placed[k][0];
+            let distance = perimeter as FloatValue * line_width;
+            let inner = offset(polygon, if hole { distance } else { -distance });
+            // Shrinking past the middle turns edges around
+            let collapsed = (0..polygon.len()).any(|i| {
+                let j = (i + 1) % polygon.len();
+                (inner[j] - inner[i]).dot(&(polygon[j] - polygon[i])) <= 0.0
+            });
+            let meets = || {
+                placed
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != k)
+                    .flat_map(|(_, polygons)| polygons)
+                    .any(|other| polygons_within(&inner, other, line_width - OVERLAP_TOLERANCE))
+            };
+            if collapsed || meets() {
+                growing[k] = None;
+                continue;
+            }
+            let mut points = inner
+                .iter()
+                .map(|point| point![point.x, point.y, ring.d])
+                .collect::<Vec<_>>();
+            points.push(points[0]);
+            walls[k].push(SlicePath {
+                i: ring.i,
+                d: ring.d,
+                axis: ring.axis,
+                closed: true,
+                aabb: aabb_from_points(points.iter()),
+                points,
+            });
+            placed[k].push(inner);
+        }
+    }
+    walls
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::point;
+
+    use crate::slicer::{
+        aabb_from_points,
+        axis::Axis,
+        perimeters::{inner_walls, outline},
+        polygon::polygons_within,
+        slice_path::SlicePath,
+    };
+
+    /// A clockwise square ring at height 1
+    fn square(min: f64, max: f64) -> SlicePath {
+        let points = vec![
+            point![min, min, 1.0],
+            point![min, max, 1.0],
+            point![max, max, 1.0],
+            point![max, min, 1.0],
+            point![min, min, 1.0],
+        ];
+        SlicePath {
+            i: 0,
+            d: 1.0,
+            axis: Axis::Z,
+            closed: true,
+            aabb: aabb_from_points(points.iter()),
+            points,
+        }
+    }
+
+    #[test]
+    fn test_inner_walls() {
+        let rings = [square(0.0, 10.0), square(4.0, 6.0)];
+        let walls = inner_walls(&rings, |_| 3, 1.0);
+        // Shrinking the outer ring
+        assert_eq!(walls[0].len(), 2);
+        assert_eq!(walls[0][1].points[0], point![2.0, 2.0, 1.0]);
+        // Growing the hole, until it would run into the walls of the outer ring
+        assert_eq!(walls[1].len(), 1);
+        assert_eq!(walls[1][0].points[0], point![3.0, 3.0, 1.0]);
+        // No two walls of different rings overlap
+        let polygons = |k: usize| {
+            std::iter::once(&rings[k])
+                .chain(&walls[k])
+                .map(outline)
+                .collect::<Vec<_>>()
+        };
+        for a in polygons(0) {
+            for b in polygons(1) {
+                assert!(!polygons_within(&a, &b, 1.0 - 1e-9));
+            }
+        }
+
+        // Collapses after the first inner wall
+        assert_eq!(inner_walls(&[square(0.0, 3.0)], |_| 4, 1.0)[0].len(), 1);
+    }
+}
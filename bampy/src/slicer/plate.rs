@@ -0,0 +1,120 @@
+use bvh::aabb::Aabb;
+use nalgebra::Vector2;
+
+use super::FloatValue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    /// The bounding boxes of two objects intersect
+    Overlap(usize, usize),
+    /// The toolhead hits the first, already printed object while printing the second
+    Toolhead(usize, usize),
+    /// An object that is not printed last reaches above the gantry
+    Gantry(usize),
+    /// The wipe tower stands in the way of an object, or of the toolhead printing it
+    WipeTower(usize),
+}
+
+/// The space around the nozzle taken up by the toolhead when printing objects one by one
+#[derive(Debug, Clone, Copy)]
+pub struct Toolhead {
+    /// Offsets of the toolhead from the nozzle towards the minimum of the X and Y axis
+    pub min: Vector2<FloatValue>,
+    /// Offsets of the toolhead from the nozzle towards the maximum of the X and Y axis
+    pub max: Vector2<FloatValue>,
+    /// Height of the gantry above the nozzle, which spans the whole bed
+    pub gantry_height: FloatValue,
+}
+
+fn overlaps_xy(a: &Aabb<FloatValue, 3>, b: &Aabb<FloatValue, 3>) -> bool {
+    a.min.x < b.max.x && b.min.x < a.max.x && a.min.y < b.max.y && b.min.y < a.max.y
+}
+
+/// The footprint of an object grown by the toolhead printing it
+fn swept(object: &Aabb<FloatValue, 3>, toolhead: &Toolhead) -> Aabb<FloatValue, 3> {
+    let mut swept = *object;
+    swept.min.x += toolhead.min.x;
+    swept.min.y += toolhead.min.y;
+    swept.max.x += toolhead.max.x;
+    swept.max.y += toolhead.max.y;
+    swept
+}
+
+/// Objects printed together must not intersect
+pub fn overlapping(objects: &[Aabb<FloatValue, 3>]) -> Vec<Collision> {
+    let mut collisions = vec![];
+    for (i, a) in objects.iter().enumerate() {
+        for (j, b) in objects.iter().enumerate().skip(i + 1) {
+            if overlaps_xy(a, b) && a.min.z < b.max.z && b.min.z < a.max.z {
+                collisions.push(Collision::Overlap(i, j));
+            }
+        }
+    }
+    collisions
+}
+
+/// Objects printed one after another in the given order must stay clear of the toolhead,
+/// which sweeps the footprint of each object grown by its size
+pub fn sequential_collisions(
+    objects: &[Aabb<FloatValue, 3>],
+    toolhead: &Toolhead,
+) -> Vec<Collision> {
+    let mut collisions = vec![];
+    for (j, object) in objects.iter().enumerate() {
+        let swept = swept(object, toolhead);
+        for (i, printed) in objects[..j].iter().enumerate() {
+            if overlaps_xy(printed, &swept) {
+                collisions.push(Collision::Toolhead(i, j));
+            }
+        }
+        if j + 1 < objects.len() && object.max.z - object.min.z > toolhead.gantry_height {
+            collisions.push(Collision::Gantry(j));
+        }
+    }
+    collisions
+}
+
+/// The wipe tower grows alongside every object. Objects printed one by one sweep the toolhead
+/// past the tower, which may already be taller than the nozzle.
+pub fn wipe_tower_collisions(
+    objects: &[Aabb<FloatValue, 3>],
+    tower: &Aabb<FloatValue, 3>,
+    toolhead: Option<&Toolhead>,
+) -> Vec<Collision> {
+    objects
+        .iter()
+        .enumerate()
+        .filter(|(_, object)| {
+            let footprint = toolhead.map_or(**object, |toolhead| swept(object, toolhead));
+            overlaps_xy(&footprint, tower)
+        })
+        .map(|(i, _)| Collision::WipeTower(i))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bvh::aabb::Aabb;
+    use nalgebra::{point, vector};
+
+    use crate::slicer::plate::{overlapping, sequential_collisions, Collision, Toolhead};
+
+    #[test]
+    fn test_collisions() {
+        let objects = [
+            Aabb::with_bounds(point![0.0, 0.0, 0.0], point![10.0, 10.0, 30.0]),
+            Aabb::with_bounds(point![15.0, 0.0, 0.0], point![25.0, 10.0, 10.0]),
+        ];
+        assert!(overlapping(&objects).is_empty());
+
+        let toolhead = Toolhead {
+            min: vector![-20.0, -10.0],
+            max: vector![10.0, 10.0],
+            gantry_height: 20.0,
+        };
+        assert_eq!(
+            sequential_collisions(&objects, &toolhead),
+            vec![Collision::Gantry(0), Collision::Toolhead(0, 1)]
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Helpers for simple polygons in the XY plane.
+//!
+//! Polygons are open, meaning the last point does not repeat the first one,
+//! and clockwise like the rings returned by [`super::base_slices::BaseSlice::find_paths`].
+
+use nalgebra::{point, Point2, Vector2};
+
+use super::FloatValue;
+
+/// Smallest miter denominator, limiting the offset of very sharp corners
+const MIN_MITER: FloatValue = 0.25;
+
+/// Positive for counter clockwise polygons
+pub fn signed_area(polygon: &[Point2<FloatValue>]) -> FloatValue {
+    let mut area = 0.0;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = &polygon[(i + 1) % polygon.len()];
+        area += a.x * b.y - b.x * a.y;
+    }
+    area / 2.0
+}
+
+/// The clockwise convex hull of the points
+pub fn convex_hull(points: &[Point2<FloatValue>]) -> Vec<Point2<FloatValue>> {
+    let mut points = points.to_vec();
+    points.sort_unstable_by(|a, b| {
+        a.x.partial_cmp(&b.x)
+            .unwrap()
+            .then(a.y.partial_cmp(&b.y).unwrap())
+    });
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+
+    let cross = |o: &Point2<FloatValue>, a: &Point2<FloatValue>, b: &Point2<FloatValue>| {
+        (a - o).perp(&(b - o))
+    };
+    let mut hull = Vec::<Point2<FloatValue>>::with_capacity(points.len() * 2);
+    for pass in 0..2 {
+        let start = hull.len();
+        let iter: Box<dyn Iterator<Item = &Point2<FloatValue>>> = if pass == 0 {
+            Box::new(points.iter())
+        } else {
+            Box::new(points.iter().rev())
+        };
+        for point in iter {
+            while hull.len() >= start + 2
+                && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], point) >= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(*point);
+        }
+        hull.pop();
+    }
+    hull
+}
+
+/// Offsets a polygon to the left hand side of its direction,
+/// which is the outside of clockwise polygons.
+///
+/// Corners are mitered, self intersections are not resolved.
+pub fn offset(polygon: &[Point2<FloatValue>], distance: FloatValue) -> Vec<Point2<FloatValue>> {
+    let mut points = polygon.to_vec();
+    points.dedup_by(|a, b| (*a - *b).norm() < FloatValue::EPSILON);
+    while points.len() > 1 && (points[0] - points[points.len() - 1]).norm() < FloatValue::EPSILON {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return points;
+    }
+
+    let normal = |a: &Point2<FloatValue>, b: &Point2<FloatValue>| {
+        let edge = (b - a).normalize();
+        Vector2::new(-edge.y, edge.x)
+    };
+    (0..points.len())
+        .map(|i| {
+            let previous = &points[(i + points.len() - 1) % points.len()];
+            let current = &points[i];
+            let next = &points[(i + 1) % points.len()];
+            let a = normal(previous, current);
+            let b = normal(current, next);
+            current + (a + b) * distance / (1.0 + a.dot(&b)).max(MIN_MITER)
+        })
+        .collect()
+}
+
+fn point_segment_distance(
+    point: &Point2<FloatValue>,
+    start: &Point2<FloatValue>,
+    end: &Point2<FloatValue>,
+) -> FloatValue {
+    let direction = end - start;
+    let length = direction.norm_squared();
+    if length < FloatValue::EPSILON {
+        return (point - start).norm();
+    }
+    let t = ((point - start).dot(&direction) / length).clamp(0.0, 1.0);
+    (point - (start + direction * t)).norm()
+}
+
+/// Whether two segments cross, not counting segments that only touch
+fn segments_cross(a: [&Point2<FloatValue>; 2], b: [&Point2<FloatValue>; 2]) -> bool {
+    let side = |p: &Point2<FloatValue>, q: &Point2<FloatValue>, r: &Point2<FloatValue>| {
+        (q - p).perp(&(r - p))
+    };
+    side(a[0], a[1], b[0]) * side(a[0], a[1], b[1]) < 0.0
+        && side(b[0], b[1], a[0]) * side(b[0], b[1], a[1]) < 0.0
+}
+
+/// The corners of the bounding box of some points
+fn bounds<'a, I>(points: I) -> (Point2<FloatValue>, Point2<FloatValue>)
+where
+    I: IntoIterator<Item = &'a Point2<FloatValue>>,
+{
+    points.into_iter().fold(
+        (
+            point![FloatValue::INFINITY, FloatValue::INFINITY],
+            point![FloatValue::NEG_INFINITY, FloatValue::NEG_INFINITY],
+        ),
+        |(min, max), point| (min.inf(point), max.sup(point)),
+    )
+}
+
+/// Whether two bounding boxes are closer than `distance` along both axes
+fn bounds_within(
+    a: &(Point2<FloatValue>, Point2<FloatValue>),
+    b: &(Point2<FloatValue>, Point2<FloatValue>),
+    distance: FloatValue,
+) -> bool {
+    a.0.x - distance < b.1.x
+        && b.0.x - distance < a.1.x
+        && a.0.y - distance < b.1.y
+        && b.0.y - distance < a.1.y
+}
+
+/// Whether the edges of two polygons come closer than `distance`, or cross
+pub fn polygons_within(
+    a: &[Point2<FloatValue>],
+    b: &[Point2<FloatValue>],
+    distance: FloatValue,
+) -> bool {
+    if a.is_empty() || b.is_empty() || !bounds_within(&bounds(a), &bounds(b), distance) {
+        return false;
+    }
+    let edges = |polygon: &[Point2<FloatValue>]| {
+        (0..polygon.len())
+            .map(|i| {
+                let edge = [polygon[i], polygon[(i + 1) % polygon.len()]];
+                (edge, bounds(&edge))
+            })
+            .collect::<Vec<_>>()
+    };
+    let b = edges(b);
+    edges(a).iter().any(|([a0, a1], a_bounds)| {
+        b.iter()
+            .filter(|(_, b_bounds)| bounds_within(a_bounds, b_bounds, distance))
+            .any(|([b0, b1], _)| {
+                segments_cross([a0, a1], [b0, b1])
+                    || point_segment_distance(a0, b0, b1) < distance
+                    || point_segment_distance(a1, b0, b1) < distance
+                    || point_segment_distance(b0, a0, a1) < distance
+                    || point_segment_distance(b1, a0, a1) < distance
+            })
+    })
+}
+
+/// Fills a polygon with parallel lines along the X axis,
+/// or along the Y axis if `vertical` is set.
+pub fn scanline_fill(
+    polygon: &[Point2<FloatValue>],
+    spacing: FloatValue,
+    vertical: bool,
+) -> Vec<(Point2<FloatValue>, Point2<FloatValue>)> {
+    scanline_fill_rings(&[polygon.to_vec()], spacing, vertical)
+}
+
+/// Fills the area enclosed by the rings with parallel lines, leaving out holes.
+/// A point is inside if a line through it crosses an odd number of ring edges on either side.
+/// Without a positive spacing there are no lines to fill with.
+pub fn scanline_fill_rings(
+    rings: &[Vec<Point2<FloatValue>>],
+    spacing: FloatValue,
+    vertical: bool,
+) -> Vec<(Point2<FloatValue>, Point2<FloatValue>)> {
+    let swap = |p: &Point2<FloatValue>| if vertical { point![p.y, p.x] } else { *p };
+    let rings = rings
+        .iter()
+        .filter(|ring| ring.len() >= 3)
+        .map(|ring| ring.iter().map(swap).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    if rings.is_empty() || spacing.is_nan() || spacing <= 0.0 {
+        return vec![];
+    }
+    let min = rings
+        .iter()
+        .flatten()
+        .map(|p| p.y)
+        .fold(FloatValue::MAX, FloatValue::min);
+    let max = rings
+        .iter()
+        .flatten()
+        .map(|p| p.y)
+        .fold(FloatValue::MIN, FloatValue::max);
+
+    let mut lines = Vec::new();
+    let mut y = min + spacing / 2.0;
+    while y < max {
+        let mut crossings = rings
+            .iter()
+            .flat_map(|ring| {
+                (0..ring.len()).filter_map(move |i| {
+                    let a = &ring[i];
+                    let b = &ring[(i + 1) % ring.len()];
+                    if (a.y > y) != (b.y > y) {
+                        Some(a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x))
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        crossings.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        for pair in crossings.chunks_exact(2) {
+            lines.push((swap(&point![pair[0], y]), swap(&point![pair[1], y])));
+        }
+        y += spacing;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::point;
+
+    use crate::slicer::polygon::{convex_hull, offset, scanline_fill, signed_area};
+
+    #[test]
+    fn test_hull_offset() {
+        let hull = convex_hull(&[
+            point![0.0, 0.0],
+            point![1.0, 0.5],
+            point![2.0, 0.0],
+            point![2.0, 2.0],
+            point![0.0, 2.0],
+        ]);
+        assert_eq!(hull.len(), 4);
+        assert!(signed_area(&hull) < 0.0);
+
+        let offset = offset(&hull, 1.0);
+        assert_relative_eq!(signed_area(&offset), -16.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_scanline_spacing() {
+        let square = [
+            point![0.0, 0.0],
+            point![2.0, 0.0],
+            point![2.0, 2.0],
+            point![0.0, 2.0],
+        ];
+        assert_eq!(scanline_fill(&square, 0.5, false).len(), 4);
+        assert!(scanline_fill(&square, 0.0, false).is_empty());
+        assert!(scanline_fill(&square, -0.5, true).is_empty());
+        assert!(scanline_fill(&square, f64::NAN, false).is_empty());
+    }
+}
@@ -38,7 +38,30 @@ impl SdfBox {
 impl Sdf<3> for SdfBox {
     fn sdf(&self, p: &Point<FloatValue, 3>) -> FloatValue {
         let q = p.coords.abs() - self.size.coords;
-        q.sup(&Vector3::zeros()).add_scalar(q.max().min(0.0)).norm()
+        q.sup(&Vector3::zeros()).norm() + q.max().min(0.0)
+    }
+}
+
+/// A cylinder along the Z axis, centred on the origin
+#[derive(Debug, Clone, Copy)]
+pub struct SdfCylinder {
+    radius: FloatValue,
+    half_height: FloatValue,
+}
+
+impl SdfCylinder {
+    pub fn new(radius: FloatValue, height: FloatValue) -> Self {
+        Self {
+            radius,
+            half_height: height / 2.0,
+        }
+    }
+}
+
+impl Sdf<3> for SdfCylinder {
+    fn sdf(&self, p: &Point<FloatValue, 3>) -> FloatValue {
+        let d = vector![p.coords.xy().norm(), p.z].abs() - vector![self.radius, self.half_height];
+        d.max().min(0.0) + d.sup(&Vector2::zeros()).norm()
     }
 }
 
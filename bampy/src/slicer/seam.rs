@@ -0,0 +1,343 @@
+use nalgebra::{vector, Point3};
+
+use super::{
+    mesh::Mesh,
+    paint::{Paint, PAINT_TOLERANCE},
+    slice_path::SlicePath,
+    triangle::Triangle,
+    FloatValue,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Seam {
+    /// Continue the seam of the closest ring of the previous layer
+    Aligned,
+    /// Start where the previous ring ended
+    Nearest,
+    /// Start at the back of the ring
+    Rear,
+    /// Hide the seam in the sharpest concave corner
+    SharpestCorner,
+    /// Start closest to a point, projected onto the slicing plane
+    Point(Point3<FloatValue>),
+    /// Start closest to a line segment, projected onto the slicing plane
+    Line(Point3<FloatValue>, Point3<FloatValue>),
+}
+
+/// Rotates closed rings so they start at the seam
+pub struct SeamPlacer {
+    seam: Seam,
+    previous: Option<Point3<FloatValue>>,
+    previous_layer: Vec<Point3<FloatValue>>,
+    enforcers: Option<Mesh>,
+    blockers: Option<Mesh>,
+}
+
+impl SeamPlacer {
+    pub fn new(seam: Seam) -> Self {
+        Self {
+            seam,
+            previous: None,
+            previous_layer: vec![],
+            enforcers: None,
+            blockers: None,
+        }
+    }
+
+    /// Keeps seams on the triangles painted as seam enforcers where a ring crosses them,
+    /// and off the ones painted as blockers where it can.
+    pub fn with_paint(mut self, triangles: &[Triangle]) -> Self {
+        let painted = |paint: Paint| {
+            let triangles = triangles
+                .iter()
+                .filter(|triangle| triangle.attributes.seam == paint)
+                .copied()
+                .collect::<Vec<_>>();
+            (!triangles.is_empty()).then(|| Mesh::from(triangles))
+        };
+        self.enforcers = painted(Paint::Enforcer);
+        self.blockers = painted(Paint::Blocker);
+        self
+    }
+
+    /// Which points of a ring the seam may be placed on
+    fn allowed(&self, points: &[Point3<FloatValue>]) -> Vec<bool> {
+        let painted = |mesh: &Mesh| {
+            points
+                .iter()
+                .map(|point| mesh.triangle_at(point, PAINT_TOLERANCE).is_some())
+                .collect::<Vec<_>>()
+        };
+        if let Some(enforcers) = &self.enforcers {
+            let enforced = painted(enforcers);
+            if enforced.contains(&true) {
+                return enforced;
+            }
+        }
+        if let Some(blockers) = &self.blockers {
+            let allowed = painted(blockers)
+                .into_iter()
+                .map(|blocked| !blocked)
+                .collect::<Vec<_>>();
+            if allowed.contains(&true) {
+                return allowed;
+            }
+        }
+        vec![true; points.len()]
+    }
+
+    /// Places the seams of all rings of a single layer, in print order
+    pub fn place_layer(&mut self, rings: &mut [SlicePath]) {
+        let holes = rings
+            .iter()
+            .enumerate()
+            .map(|(i, ring)| {
+                let Some(point) = ring.points.first() else {
+                    return false;
+                };
+                rings
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, other)| i != *j && other.closed && other.contains(point))
+                    .count()
+                    % 2
+                    == 1
+            })
+            .collect::<Vec<_>>();
+
+        let mut layer = Vec::with_capacity(rings.len());
+        for (ring, hole) in rings.iter_mut().zip(holes) {
+            if !ring.closed || ring.points.len() < 3 {
+                continue;
+            }
+            let index = self.seam_index(ring, hole);
+            rotate(&mut ring.points, index);
+            self.previous = Some(ring.points[0]);
+            layer.push(ring.points[0]);
+        }
+        self.previous_layer = layer;
+    }
+
+    fn seam_index(&self, ring: &SlicePath, hole: bool) -> usize {
+        let (axis_a, axis_b) = ring.axis.other();
+        let (a, b) = (axis_a as usize, axis_b as usize);
+        let points = &ring.points[..ring.points.len() - 1];
+        let allowed = self.allowed(points);
+        let planar_distance =
+            |p: &Point3<FloatValue>, q: &Point3<FloatValue>| (p[a] - q[a]).hypot(p[b] - q[b]);
+        let closest = |distance: &dyn Fn(&Point3<FloatValue>) -> FloatValue| {
+            points
+                .iter()
+                .map(distance)
+                .enumerate()
+                .filter(|(i, _)| allowed[*i])
+                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+                .map(|(i, _)| i)
+                .unwrap_or(0)
+        };
+
+        match self.seam {
+            Seam::Aligned if !self.previous_layer.is_empty() => closest(&|point| {
+                self.previous_layer
+                    .iter()
+                    .map(|seam| planar_distance(point, seam))
+                    .fold(FloatValue::MAX, FloatValue::min)
+            }),
+            Seam::Nearest if self.previous.is_some() => {
+                let previous = self.previous.unwrap();
+                closest(&|point| (point - previous).norm())
+            }
+            Seam::Rear => points
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| allowed[*i])
+                .max_by(|(_, p), (_, q)| {
+                    p[b].partial_cmp(&q[b])
+                        .unwrap()
+                        .then(q[a].partial_cmp(&p[a]).unwrap())
+                })
+                .map(|(i, _)| i)
+                .unwrap_or(0),
+            Seam::Point(seam) => closest(&|point| planar_distance(point, &seam)),
+            Seam::Line(start, end) => {
+                let planar = |p: &Point3<FloatValue>| vector![p[a], p[b]];
+                let (start, direction) = (planar(&start), planar(&end) - planar(&start));
+                closest(&|point| {
+                    let t = if direction.norm_squared() > 0.0 {
+                        ((planar(point) - start).dot(&direction) / direction.norm_squared())
+                            .clamp(0.0, 1.0)
+                    } else {
+                        0.0
+                    };
+                    (planar(point) - (start + direction * t)).norm()
+                })
+            }
+            _ => sharpest_corner(points, &allowed, a, b, hole),
+        }
+    }
+}
+
+/// Finds the sharpest concave corner of a clockwise ring without its closing point,
+/// falling back to the sharpest convex corner.
+fn sharpest_corner(
+    points: &[Point3<FloatValue>],
+    allowed: &[bool],
+    a: usize,
+    b: usize,
+    hole: bool,
+) -> usize {
+    // Rings are clockwise, so the material is on the right hand side of outer walls
+    // and on the left hand side of holes.
+    let sign = if hole { -1.0 } else { 1.0 };
+    let turns = (0..points.len())
+        .map(|i| {
+            let previous = &points[(i + points.len() - 1) % points.len()];
+            let current = &points[i];
+            let next = &points[(i + 1) % points.len()];
+            let (ax, ay) = (current[a] - previous[a], current[b] - previous[b]);
+            let (bx, by) = (next[a] - current[a], next[b] - current[b]);
+            sign * (ax * by - ay * bx).atan2(ax * bx + ay * by)
+        })
+        .collect::<Vec<_>>();
+    let concave = turns
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| allowed[*i])
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+    match concave {
+        Some((i, turn)) if *turn > 0.0 => i,
+        _ => turns
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| allowed[*i])
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .map(|(i, _)| i)
+            .unwrap_or(0),
+    }
+}
+
+/// Rotates a closed ring, whose last point repeats the first one, to start at `index`
+fn rotate(points: &mut Vec<Point3<FloatValue>>, index: usize) {
+    if index == 0 {
+        return;
+    }
+    points.pop();
+    points.rotate_left(index);
+    points.push(points[0]);
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{point, Point3};
+
+    use crate::slicer::{
+        aabb_from_points,
+        axis::Axis,
+        paint::Paint,
+        seam::{Seam, SeamPlacer},
+        slice_path::SlicePath,
+        triangle::Triangle,
+    };
+
+    fn ring(points: Vec<Point3<f64>>) -> SlicePath {
+        SlicePath {
+            axis: Axis::Z,
+            closed: true,
+            aabb: aabb_from_points(points.iter()),
+            points,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sharpest_concave_corner() {
+        // clockwise L shape with a single concave corner at (1, 1)
+        let mut rings = [ring(vec![
+            point![0.0, 0.0, 0.0],
+            point![0.0, 2.0, 0.0],
+            point![1.0, 2.0, 0.0],
+            point![1.0, 1.0, 0.0],
+            point![2.0, 1.0, 0.0],
+            point![2.0, 0.0, 0.0],
+            point![0.0, 0.0, 0.0],
+        ])];
+
+        SeamPlacer::new(Seam::SharpestCorner).place_layer(&mut rings);
+
+        assert_eq!(rings[0].points.first(), Some(&point![1.0, 1.0, 0.0]));
+        assert_eq!(rings[0].points.last(), Some(&point![1.0, 1.0, 0.0]));
+        assert_eq!(rings[0].points.len(), 7);
+    }
+
+    #[test]
+    fn test_rear_seam() {
+        let mut rings = [ring(vec![
+            point![0.0, 0.0, 0.0],
+            point![0.0, 2.0, 0.0],
+            point![2.0, 2.0, 0.0],
+            point![2.0, 0.0, 0.0],
+            point![0.0, 0.0, 0.0],
+        ])];
+
+        SeamPlacer::new(Seam::Rear).place_layer(&mut rings);
+
+        assert_eq!(rings[0].points.first(), Some(&point![0.0, 2.0, 0.0]));
+    }
+
+    #[test]
+    fn test_line_seam() {
+        let mut rings = [
+            ring(vec![]),
+            ring(vec![
+                point![0.0, 0.0, 0.0],
+                point![0.0, 2.0, 0.0],
+                point![2.0, 2.0, 0.0],
+                point![2.0, 0.0, 0.0],
+                point![0.0, 0.0, 0.0],
+            ]),
+        ];
+
+        // Far above the ring, only its projection counts
+        SeamPlacer::new(Seam::Line(point![3.0, 1.5, 10.0], point![3.0, 3.0, 50.0]))
+            .place_layer(&mut rings);
+
+        assert!(rings[0].points.is_empty());
+        assert_eq!(rings[1].points.first(), Some(&point![2.0, 2.0, 0.0]));
+    }
+
+    #[test]
+    fn test_painted_seam() {
+        let square = || {
+            [ring(vec![
+                point![0.0, 0.0, 0.0],
+                point![0.0, 2.0, 0.0],
+                point![2.0, 2.0, 0.0],
+                point![2.0, 0.0, 0.0],
+                point![0.0, 0.0, 0.0],
+            ])]
+        };
+        // A wall below the ring, crossing it at the front right corner
+        let mut wall = Triangle::new(
+            point![1.5, -0.5, -1.0],
+            point![2.5, 0.5, -1.0],
+            point![2.0, 0.0, 1.0],
+        );
+        wall.attributes.seam = Paint::Enforcer;
+
+        let mut rings = square();
+        SeamPlacer::new(Seam::Rear)
+            .with_paint(&[wall])
+            .place_layer(&mut rings);
+        assert_eq!(rings[0].points.first(), Some(&point![2.0, 0.0, 0.0]));
+
+        // Moved to the rear left corner, where the seam would be without paint
+        let offset = nalgebra::vector![-2.0, 2.0, 0.0];
+        let mut wall = Triangle::new(wall.a + offset, wall.b + offset, wall.c + offset);
+        wall.attributes.seam = Paint::Blocker;
+        let mut rings = square();
+        SeamPlacer::new(Seam::Rear)
+            .with_paint(&[wall])
+            .place_layer(&mut rings);
+        assert_eq!(rings[0].points.first(), Some(&point![2.0, 2.0, 0.0]));
+    }
+}
@@ -0,0 +1,153 @@
+use nalgebra::Point3;
+
+use super::{toolpath::ToolPath, FloatValue};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SimplifySettings {
+    /// Maximum distance of the removed points from the simplified path
+    pub max_deviation: FloatValue,
+    /// Points closer than this to the previous point are removed, even if they deviate further
+    pub min_segment_length: FloatValue,
+}
+
+fn segment_distance(
+    point: &Point3<FloatValue>,
+    start: &Point3<FloatValue>,
+    end: &Point3<FloatValue>,
+) -> FloatValue {
+    let direction = end - start;
+    let length = direction.norm_squared();
+    if length < FloatValue::EPSILON {
+        return (point - start).norm();
+    }
+    let t = ((point - start).dot(&direction) / length).clamp(0.0, 1.0);
+    (point - (start + direction * t)).norm()
+}
+
+/// Ramer–Douglas–Peucker in 3D, marking the points that are kept
+fn douglas_peucker(points: &[Point3<FloatValue>], max_deviation: FloatValue, keep: &mut [bool]) {
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = stack.pop() {
+        if end <= start + 1 {
+            continue;
+        }
+        let (index, distance) = (start + 1..end)
+            .map(|i| {
+                (
+                    i,
+                    segment_distance(&points[i], &points[start], &points[end]),
+                )
+            })
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .unwrap();
+        if distance > max_deviation {
+            keep[index] = true;
+            stack.push((start, index));
+            stack.push((index, end));
+        }
+    }
+}
+
+/// Removes points that barely change the shape of a path.
+///
+/// Both ends are kept, so closed paths stay closed. Merged segments are extruded
+/// with the length weighted average of their flows.
+pub fn simplify(path: ToolPath, settings: &SimplifySettings) -> ToolPath {
+    let points = &path.points;
+    if points.len() < 3 {
+        return path;
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    douglas_peucker(points, settings.max_deviation, &mut keep);
+
+    let mut previous = 0;
+    for i in 1..points.len() - 1 {
+        if keep[i] && (points[i] - points[previous]).norm() < settings.min_segment_length {
+            keep[i] = false;
+        } else if keep[i] {
+            previous = i;
+        }
+    }
+    // The end is never moved, so a short last segment gives up its start instead
+    let last = points.len() - 1;
+    if previous != 0 && (points[last] - points[previous]).norm() < settings.min_segment_length {
+        keep[previous] = false;
+    }
+
+    let mut simplified = Vec::with_capacity(points.len());
+    let mut flow = Vec::with_capacity(path.flow.len());
+    let (mut length, mut volume) = (0.0, 0.0);
+    for i in 0..points.len() {
+        if i > 0 {
+            let segment = (points[i] - points[i - 1]).norm();
+            length += segment;
+            volume += segment * path.flow.get(i - 1).copied().unwrap_or(1.0);
+        }
+        if keep[i] {
+            if i > 0 {
+                flow.push(if length > 0.0 {
+                    volume / length
+                } else {
+                    path.flow.get(i - 1).copied().unwrap_or(1.0)
+                });
+            }
+            simplified.push(points[i]);
+            length = 0.0;
+            volume = 0.0;
+        }
+    }
+    ToolPath {
+        points: simplified,
+        flow,
+        ..path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::point;
+
+    use crate::slicer::{
+        simplify::{simplify, SimplifySettings},
+        toolpath::ToolPath,
+    };
+
+    #[test]
+    fn test_simplify() {
+        let path = ToolPath {
+            flow: vec![1.0, 2.0, 1.0, 1.0, 1.0],
+            ..ToolPath::planar(
+                vec![
+                    point![0.0, 0.0, 0.0],
+                    point![1.0, 0.001, 0.0],
+                    point![2.0, 0.0, 0.0],
+                    point![2.0, 1.0, 0.5],
+                    point![2.0, 1.01, 0.5],
+                    point![2.0, 2.0, 1.0],
+                ],
+                1.0,
+                false,
+            )
+        };
+        let settings = SimplifySettings {
+            max_deviation: 0.01,
+            min_segment_length: 0.05,
+        };
+
+        let simplified = simplify(path, &settings);
+        assert_eq!(
+            simplified.points,
+            vec![
+                point![0.0, 0.0, 0.0],
+                point![2.0, 0.0, 0.0],
+                point![2.0, 2.0, 1.0]
+            ]
+        );
+        assert_eq!(simplified.flow.len(), 2);
+        assert_relative_eq!(simplified.flow[0], 1.5, epsilon = 1e-3);
+        assert_relative_eq!(simplified.flow[1], 1.0);
+    }
+}
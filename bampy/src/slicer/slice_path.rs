@@ -4,7 +4,7 @@ use approx::relative_eq;
 use bvh::aabb::Aabb;
 use nalgebra::Point3;
 
-use super::{axis::Axis, mesh::Mesh, FloatValue};
+use super::{axis::Axis, job::Job, mesh::Mesh, FloatValue};
 
 #[derive(Debug, Default)]
 pub struct SlicePath {
@@ -17,6 +17,25 @@ pub struct SlicePath {
     pub aabb: Aabb<FloatValue, 3>,
 }
 
+impl SlicePath {
+    /// Checks if the point lies inside the ring, projected onto the slicing plane
+    pub fn contains(&self, point: &Point3<FloatValue>) -> bool {
+        let (axis_a, axis_b) = self.axis.other();
+        let (a, b) = (axis_a as usize, axis_b as usize);
+        let mut inside = false;
+        for pair in self.points.windows(2) {
+            let (start, end) = (&pair[0], &pair[1]);
+            if (start[b] > point[b]) != (end[b] > point[b])
+                && point[a]
+                    < start[a] + (point[b] - start[b]) / (end[b] - start[b]) * (end[a] - start[a])
+            {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+}
+
 pub struct SurfacePath {
     pub i: RangeInclusive<usize>,
     pub d: RangeInclusive<FloatValue>,
@@ -32,12 +51,12 @@ pub struct SurfacePathIterator {
 }
 
 impl SurfacePathIterator {
-    pub fn new(mesh: &Mesh, axis: Axis, nozzle_width: FloatValue) -> Self {
+    pub fn new(mesh: &Mesh, axis: Axis, nozzle_width: FloatValue, job: &Job) -> Self {
         let (h_axis, _) = axis.other();
 
         Self {
             slices: mesh
-                .slice_paths(axis, nozzle_width)
+                .slice_paths(axis, mesh.layer_positions(axis, nozzle_width), job)
                 .map(|mut slice| {
                     for ring in &mut slice {
                         ring.points.sort_unstable_by(|a, b| {
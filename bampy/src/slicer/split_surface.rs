@@ -1,65 +1,339 @@
-use super::{mesh::Mesh, triangle::Triangle};
-use bvh::bvh::{Bvh, BvhNode};
+use std::collections::{HashMap, HashSet};
+
+use approx::relative_eq;
+use nalgebra::{Point3, Vector3};
+
+use super::{
+    job::{Cancelled, Job},
+    mesh::Mesh,
+    triangle::Triangle,
+    FloatValue,
+};
+
+/// Triangles processed between checks for cancellation
+const CHECK_INTERVAL: usize = 4096;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Triangles that share an edge are joined, and so are surfaces that meet at more than
+    /// one vertex without sharing an edge, like along T-junctions.
+    /// Surfaces that touch at a single vertex stay apart.
+    #[default]
+    Junction,
+    /// Only triangles that share an edge are joined
+    Edge,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SplitSettings {
+    pub connectivity: Connectivity,
+    /// Triangles whose normals differ by more than this angle are split at their shared edge
+    pub max_crease_angle: Option<FloatValue>,
+}
+
+/// Checks if a triangle faces up within the maximum angle of non-planar surfaces
+pub fn is_top_surface(
+    triangle: &Triangle,
+    bed_normal: &Vector3<FloatValue>,
+    max_angle: FloatValue,
+) -> bool {
+    let angle = triangle.normal.angle(bed_normal);
+    angle <= max_angle || relative_eq!(angle, max_angle)
+}
+
+/// Checks if a surface covers at least the given area, stopping as soon as it does
+pub fn has_min_area(mesh: &Mesh, min_area: FloatValue) -> bool {
+    let mut area = 0.0;
+    for triangle in &mesh.triangles {
+        area += triangle.area();
+        if area >= min_area {
+            return true;
+        }
+    }
+    false
+}
+
+type VertexKey = [u64; 3];
+
+/// Vertices are matched exactly, as they are in the meshes of common file formats
+fn vertex_key(point: &Point3<FloatValue>) -> VertexKey {
+    // Adding zero turns negative zero into positive zero
+    [
+        (point.x + 0.0).to_bits(),
+        (point.y + 0.0).to_bits(),
+        (point.z + 0.0).to_bits(),
+    ]
+}
+
+struct DisjointSet {
+    parents: Vec<usize>,
+    ranks: Vec<u8>,
+}
+
+impl DisjointSet {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+            ranks: vec![0; len],
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        match self.ranks[a].cmp(&self.ranks[b]) {
+            std::cmp::Ordering::Less => self.parents[a] = b,
+            std::cmp::Ordering::Greater => self.parents[b] = a,
+            std::cmp::Ordering::Equal => {
+                self.parents[b] = a;
+                self.ranks[a] += 1;
+            }
+        }
+    }
+}
 
 /// Splits a surface into connected surfaces.
-/// TODO: self intersections
-pub fn split_surface(mut triangles: Vec<Triangle>) -> Vec<Mesh> {
-    let mut surfaces = vec![];
-    while let Some(triangle) = triangles.pop() {
-        let mut surface = vec![triangle];
-        let mut bvh = Bvh::build(&mut surface);
-        let mut aabb = surface[0].aabb.clone();
-
-        loop {
-            let mut mutated = false;
-            triangles.retain_mut(|triangle| {
-                let mut stack = Vec::<usize>::new();
-                stack.push(0);
-                while let Some(i) = stack.pop() {
-                    match bvh.nodes[i] {
-                        BvhNode::Node {
-                            parent_index: _,
-                            child_l_index,
-                            child_l_aabb,
-                            child_r_index,
-                            child_r_aabb,
-                        } => {
-                            if triangle.has_point_in_aabb(&child_l_aabb) {
-                                stack.push(child_l_index);
-                            }
-                            if triangle.has_point_in_aabb(&child_r_aabb) {
-                                stack.push(child_r_index);
-                            }
-                        }
-                        BvhNode::Leaf {
-                            parent_index: _,
-                            shape_index,
-                        } => {
-                            if triangle.shares_point_with_triangle(surface[shape_index]) {
-                                mutated = true;
-                                surface.push(*triangle);
-                                let index = surface.len() - 1;
-                                bvh.add_shape(&mut surface, index);
-                                aabb.join_mut(&triangle.aabb);
-                                return false;
-                            }
-                        }
-                    }
+///
+/// Triangles are connected through shared edges. Surfaces that only touch at a single vertex
+/// are kept apart, as printing them in one go would cross between them at that vertex.
+/// With a crease angle, sharp edges split the surface into patches that are rasterised separately.
+/// Triangles painted with different extruders always end up in separate surfaces.
+pub fn split_surface(
+    triangles: Vec<Triangle>,
+    settings: &SplitSettings,
+    job: &Job,
+) -> Result<Vec<Mesh>, Cancelled> {
+    let mut vertices = HashMap::<VertexKey, Vec<usize>>::with_capacity(triangles.len() / 2);
+    let mut edges =
+        HashMap::<(VertexKey, VertexKey), usize>::with_capacity(triangles.len() * 3 / 2);
+    let mut set = DisjointSet::new(triangles.len());
+    let mut creases = HashSet::<VertexKey>::new();
+    for (i, triangle) in triangles.iter().enumerate() {
+        if i % CHECK_INTERVAL == 0 {
+            job.check()?;
+        }
+        let keys = [
+            vertex_key(&triangle.a),
+            vertex_key(&triangle.b),
+            vertex_key(&triangle.c),
+        ];
+        for (j, key) in keys.iter().enumerate() {
+            vertices.entry(*key).or_default().push(i);
+            let other = keys[(j + 1) % 3];
+            let edge = if *key < other {
+                (*key, other)
+            } else {
+                (other, *key)
+            };
+            if let Some(neighbour) = edges.insert(edge, i) {
+                let crease = settings.max_crease_angle.is_some_and(|max_angle| {
+                    triangles[neighbour].normal.angle(&triangle.normal) > max_angle
+                }) || triangles[neighbour].attributes.extruder
+                    != triangle.attributes.extruder;
+                if crease {
+                    creases.insert(edge.0);
+                    creases.insert(edge.1);
+                } else {
+                    set.union(neighbour, i);
                 }
-                true
-            });
-            if !mutated {
-                break;
             }
         }
+    }
+    job.check()?;
+
+    // Surfaces that share more than one vertex without sharing an edge
+    // still belong together, for example along T-junctions
+    let mut shared = HashMap::<(usize, usize), usize>::new();
+    let vertex_connected = vertices.iter().filter(|(key, _)| {
+        settings.connectivity == Connectivity::Junction && !creases.contains(*key)
+    });
+    for (_, touching) in vertex_connected {
+        let mut roots = touching.iter().map(|i| set.find(*i)).collect::<Vec<_>>();
+        roots.sort_unstable();
+        roots.dedup();
+        for (k, a) in roots.iter().enumerate() {
+            for b in &roots[k + 1..] {
+                *shared.entry((*a, *b)).or_default() += 1;
+            }
+        }
+    }
+    for ((a, b), count) in shared {
+        if count > 1 {
+            set.union(a, b);
+        }
+    }
+    job.check()?;
+
+    let mut surfaces = Vec::<Vec<Triangle>>::new();
+    let mut surface_indices = HashMap::<usize, usize>::new();
+    for (i, triangle) in triangles.into_iter().enumerate() {
+        let root = set.find(i);
+        let index = *surface_indices.entry(root).or_insert_with(|| {
+            surfaces.push(vec![]);
+            surfaces.len() - 1
+        });
+        surfaces[index].push(triangle);
+    }
+
+    Ok(surfaces
+        .into_iter()
+        .filter(|surface| surface.len() > 1)
+        .map(Mesh::from)
+        .collect())
+}
 
-        if surface.len() > 1 {
-            surfaces.push(Mesh {
-                triangles: surface,
-                aabb,
-                bvh,
-            })
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use nalgebra::point;
+    use test::Bencher;
+
+    use crate::slicer::{
+        job::Job,
+        split_surface::{split_surface, Connectivity, SplitSettings},
+        triangle::Triangle,
+        FloatValue,
+    };
+
+    /// A flat grid of `size` by `size` quads, starting at `x`
+    fn grid(x: FloatValue, size: usize) -> Vec<Triangle> {
+        let mut triangles = vec![];
+        for i in 0..size {
+            for j in 0..size {
+                let (x, y) = (x + i as FloatValue, j as FloatValue);
+                let a = point![x, y, 0.0];
+                let b = point![x + 1.0, y, 0.0];
+                let c = point![x + 1.0, y + 1.0, 0.0];
+                let d = point![x, y + 1.0, 0.0];
+                triangles.push(Triangle::new(a, b, c));
+                triangles.push(Triangle::new(a, c, d));
+            }
         }
+        triangles
+    }
+
+    #[test]
+    fn test_shared_edge() {
+        let mut triangles = grid(0.0, 1);
+        triangles.extend(grid(1.0, 1));
+
+        assert_eq!(
+            split_surface(triangles, &SplitSettings::default(), &Job::new())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_material_boundary() {
+        let mut triangles = grid(0.0, 1);
+        for mut triangle in grid(1.0, 1) {
+            triangle.attributes.extruder = Some(2);
+            triangles.push(triangle);
+        }
+
+        assert_eq!(
+            split_surface(triangles, &SplitSettings::default(), &Job::new())
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_single_vertex() {
+        let mut triangles = grid(0.0, 1);
+        // Only touches the first quad at (1, 1)
+        triangles.push(Triangle::new(
+            point![1.0, 1.0, 0.0],
+            point![2.0, 1.0, 0.0],
+            point![2.0, 2.0, 0.0],
+        ));
+        triangles.push(Triangle::new(
+            point![1.0, 1.0, 0.0],
+            point![2.0, 2.0, 0.0],
+            point![1.0, 2.0, 0.0],
+        ));
+
+        let surfaces = split_surface(triangles, &SplitSettings::default(), &Job::new()).unwrap();
+        assert_eq!(surfaces.len(), 2);
+        assert!(surfaces.iter().all(|surface| surface.triangles.len() == 2));
+    }
+
+    #[test]
+    fn test_t_junction() {
+        let mut triangles = grid(0.0, 1);
+        // Two quads along the right edge of the first one, meeting it at a T-junction
+        for (min, max) in [(0.0, 0.5), (0.5, 1.0)] {
+            let a = point![1.0, min, 0.0];
+            let b = point![2.0, min, 0.0];
+            let c = point![2.0, max, 0.0];
+            let d = point![1.0, max, 0.0];
+            triangles.push(Triangle::new(a, b, c));
+            triangles.push(Triangle::new(a, c, d));
+        }
+
+        let split = |connectivity: Connectivity| {
+            let settings = SplitSettings {
+                connectivity,
+                ..Default::default()
+            };
+            split_surface(triangles.clone(), &settings, &Job::new())
+                .unwrap()
+                .len()
+        };
+        assert_eq!(split(Connectivity::Junction), 1);
+        assert_eq!(split(Connectivity::Edge), 2);
+    }
+
+    #[bench]
+    fn bench_split_surface(b: &mut Bencher) {
+        let mut triangles = grid(0.0, 100);
+        triangles.extend(grid(200.0, 100));
+        b.iter(|| {
+            split_surface(triangles.clone(), &SplitSettings::default(), &Job::new()).unwrap()
+        });
+    }
+
+    #[test]
+    fn test_crease() {
+        let mut triangles = grid(0.0, 2);
+        // Folded up by 90 degrees along x = 2
+        for triangle in grid(0.0, 2) {
+            let fold = |point: nalgebra::Point3<FloatValue>| point![2.0, point.y, point.x];
+            triangles.push(Triangle::new(
+                fold(triangle.a),
+                fold(triangle.c),
+                fold(triangle.b),
+            ));
+        }
+
+        let edge = SplitSettings {
+            connectivity: Connectivity::Edge,
+            ..Default::default()
+        };
+        let crease = SplitSettings {
+            max_crease_angle: Some(std::f64::consts::FRAC_PI_4),
+            ..Default::default()
+        };
+        let split = |settings: &SplitSettings| {
+            split_surface(triangles.clone(), settings, &Job::new())
+                .unwrap()
+                .len()
+        };
+        assert_eq!(split(&edge), 1);
+        assert_eq!(split(&crease), 2);
     }
-    surfaces
 }
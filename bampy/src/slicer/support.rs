@@ -0,0 +1,372 @@
+use approx::relative_eq;
+use bvh::bvh::BvhNode;
+use nalgebra::{point, Vector3};
+
+use super::{
+    aabb_from_points, axis::Axis, mesh::Mesh, paint::Paint, slice_path::SlicePath,
+    triangle::Triangle, FloatValue,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupportPattern {
+    /// Parallel lines along the X axis
+    Lines,
+    /// Lines along both the X and Y axis
+    Grid,
+}
+
+/// Checks if a triangle faces down further than the overhang angle,
+/// which is measured from the build direction towards the bed.
+pub fn is_overhang(
+    triangle: &Triangle,
+    bed_normal: &Vector3<FloatValue>,
+    max_overhang_angle: FloatValue,
+) -> bool {
+    triangle.normal.angle(&-bed_normal) < std::f64::consts::FRAC_PI_2 - max_overhang_angle
+}
+
+/// Checks if a triangle needs support, with support paint taking precedence over its angle
+pub fn needs_support(
+    triangle: &Triangle,
+    bed_normal: &Vector3<FloatValue>,
+    max_overhang_angle: FloatValue,
+) -> bool {
+    match triangle.attributes.support {
+        Paint::Enforcer => triangle.normal.dot(bed_normal) < 0.0,
+        Paint::Blocker => false,
+        Paint::None => is_overhang(triangle, bed_normal, max_overhang_angle),
+    }
+}
+
+/// All intersections of a vertical line with the mesh,
+/// as pairs of height and the vertical component of the normal, from bottom to top.
+pub fn vertical_hits(mesh: &Mesh, x: FloatValue, y: FloatValue) -> Vec<(FloatValue, FloatValue)> {
+    vertical_hit_triangles(mesh, x, y)
+        .into_iter()
+        .map(|(z, triangle)| (z, triangle.normal.z))
+        .collect()
+}
+
+/// All intersections of a vertical line with the mesh,
+/// as pairs of height and the triangle that was hit, from bottom to top.
+pub fn vertical_hit_triangles(
+    mesh: &Mesh,
+    x: FloatValue,
+    y: FloatValue,
+) -> Vec<(FloatValue, &Triangle)> {
+    let mut hits = Vec::new();
+    if mesh.bvh.nodes.is_empty() {
+        return hits;
+    }
+    let contains = |aabb: &bvh::aabb::Aabb<FloatValue, 3>| {
+        x >= aabb.min.x && x <= aabb.max.x && y >= aabb.min.y && y <= aabb.max.y
+    };
+    let mut stack = Vec::<usize>::new();
+    stack.push(0);
+    while let Some(i) = stack.pop() {
+        match mesh.bvh.nodes[i] {
+            BvhNode::Node {
+                parent_index: _,
+                child_l_index,
+                child_l_aabb,
+                child_r_index,
+                child_r_aabb,
+            } => {
+                if contains(&child_l_aabb) {
+                    stack.push(child_l_index);
+                }
+                if contains(&child_r_aabb) {
+                    stack.push(child_r_index);
+                }
+            }
+            BvhNode::Leaf {
+                parent_index: _,
+                shape_index,
+            } => {
+                let triangle = &mesh.triangles[shape_index];
+                if let Some(z) = triangle.z_at(x, y) {
+                    hits.push((z, triangle));
+                }
+            }
+        }
+    }
+    hits.sort_unstable_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    // Lines through shared edges hit both triangles
+    hits.dedup_by(|a, b| relative_eq!(a.0, b.0) && a.1.normal.z.signum() == b.1.normal.z.signum());
+    hits
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SupportSettings {
+    /// Steepest printable overhang, measured from the vertical
+    pub max_overhang_angle: FloatValue,
+    /// Distance between support lines, or between the contact points of tree supports
+    pub spacing: FloatValue,
+    /// Vertical gap between the support and the model
+    pub gap: FloatValue,
+    /// Maximum lean of a tree support branch from the vertical
+    pub branch_angle: FloatValue,
+    /// Diameter of a single tree support branch
+    pub branch_diameter: FloatValue,
+}
+
+/// A regular grid of support positions covering the mesh
+#[derive(Debug, Clone, Copy)]
+pub struct SupportGrid {
+    min_x: FloatValue,
+    min_y: FloatValue,
+    spacing: FloatValue,
+    pub size_x: usize,
+    pub size_y: usize,
+}
+
+impl SupportGrid {
+    pub fn new(mesh: &Mesh, spacing: FloatValue) -> Self {
+        Self {
+            min_x: mesh.aabb.min.x,
+            min_y: mesh.aabb.min.y,
+            spacing,
+            size_x: ((mesh.aabb.max.x - mesh.aabb.min.x) / spacing).ceil() as usize,
+            size_y: ((mesh.aabb.max.y - mesh.aabb.min.y) / spacing).ceil() as usize,
+        }
+    }
+
+    pub fn x(&self, x: usize) -> FloatValue {
+        self.min_x + (x as FloatValue + 0.5) * self.spacing
+    }
+
+    pub fn y(&self, y: usize) -> FloatValue {
+        self.min_y + (y as FloatValue + 0.5) * self.spacing
+    }
+}
+
+/// A vertical column of support below an overhang
+#[derive(Debug, Clone, Copy)]
+pub struct SupportColumn {
+    pub x: usize,
+    pub y: usize,
+    pub bottom: FloatValue,
+    pub top: FloatValue,
+}
+
+/// Finds the support columns at every grid point, each reaching
+/// from the bed or the model below up to `gap` below an overhang.
+pub fn support_columns(
+    mesh: &Mesh,
+    grid: &SupportGrid,
+    settings: &SupportSettings,
+    bed_normal: &Vector3<FloatValue>,
+) -> Vec<SupportColumn> {
+    let downwards = -bed_normal.z;
+    let gap = settings.gap;
+
+    let mut columns = Vec::<SupportColumn>::new();
+    for x in 0..grid.size_x {
+        for y in 0..grid.size_y {
+            let hits = vertical_hit_triangles(mesh, grid.x(x), grid.y(y));
+            for (i, (z, triangle)) in hits.iter().enumerate() {
+                if !needs_support(triangle, bed_normal, settings.max_overhang_angle) {
+                    continue;
+                }
+                let bottom = match i.checked_sub(1).map(|i| hits[i]) {
+                    None => mesh.aabb.min.z,
+                    Some((below, triangle)) if triangle.normal.z * downwards < 0.0 => below + gap,
+                    Some(_) => continue,
+                };
+                if z - gap > bottom {
+                    columns.push(SupportColumn {
+                        x,
+                        y,
+                        bottom,
+                        top: z - gap,
+                    });
+                }
+            }
+        }
+    }
+    columns
+}
+
+/// Generates planar support lines on a regular grid below all overhangs of the mesh,
+/// connecting adjacent supported grid points of each layer into lines.
+pub fn generate_support(
+    mesh: &Mesh,
+    layers: &[FloatValue],
+    pattern: SupportPattern,
+    settings: &SupportSettings,
+    bed_normal: &Vector3<FloatValue>,
+) -> Vec<SlicePath> {
+    let grid = SupportGrid::new(mesh, settings.spacing);
+    let columns = support_columns(mesh, &grid, settings, bed_normal);
+    let (size_x, size_y) = (grid.size_x, grid.size_y);
+
+    let mut paths = Vec::new();
+    let mut cells = vec![false; size_x * size_y];
+    for (i, layer) in layers.iter().enumerate() {
+        cells.iter_mut().for_each(|cell| *cell = false);
+        for column in &columns {
+            if column.bottom < *layer && *layer <= column.top {
+                cells[column.x * size_y + column.y] = true;
+            }
+        }
+
+        let mut push_line = |start: (FloatValue, FloatValue), end: (FloatValue, FloatValue)| {
+            let points = vec![
+                point![start.0, start.1, *layer],
+                point![end.0, end.1, *layer],
+            ];
+            paths.push(SlicePath {
+                i,
+                d: *layer,
+                axis: Axis::Z,
+                closed: false,
+                aabb: aabb_from_points(points.iter()),
+                points,
+            });
+        };
+
+        // Single cells are printed as a line across their width, so narrow overhangs
+        // are supported as well. The lines along X already cover them.
+        let half = grid.spacing / 2.0;
+        for y in 0..size_y {
+            let mut start = None;
+            for x in 0..=size_x {
+                match (start, x < size_x && cells[x * size_y + y]) {
+                    (None, true) => start = Some(x),
+                    (Some(first), false) => {
+                        if x - 1 > first {
+                            push_line((grid.x(first), grid.y(y)), (grid.x(x - 1), grid.y(y)));
+                        } else {
+                            push_line(
+                                (grid.x(first) - half, grid.y(y)),
+                                (grid.x(first) + half, grid.y(y)),
+                            );
+                        }
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if pattern == SupportPattern::Grid {
+            for x in 0..size_x {
+                let mut start = None;
+                for y in 0..=size_y {
+                    match (start, y < size_y && cells[x * size_y + y]) {
+                        (None, true) => start = Some(y),
+                        (Some(first), false) => {
+                            if y - 1 > first {
+                                push_line((grid.x(x), grid.y(first)), (grid.x(x), grid.y(y - 1)));
+                            }
+                            start = None;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use nalgebra::{point, vector, Point3};
+
+    use crate::slicer::{
+        mesh::Mesh,
+        support::{
+            generate_support, support_columns, SupportGrid, SupportPattern, SupportSettings,
+        },
+        triangle::Triangle,
+    };
+
+    /// Two triangles spanning a rectangle at a constant height, facing up or down
+    fn quad(min: (f64, f64), max: (f64, f64), z: f64, up: bool) -> Vec<Triangle> {
+        let corners = [
+            point![min.0, min.1, z],
+            point![max.0, min.1, z],
+            point![max.0, max.1, z],
+            point![min.0, max.1, z],
+        ];
+        let triangle = |a: Point3<f64>, b: Point3<f64>, c: Point3<f64>| {
+            if up {
+                Triangle::new(a, b, c)
+            } else {
+                Triangle::new(a, c, b)
+            }
+        };
+        vec![
+            triangle(corners[0], corners[1], corners[2]),
+            triangle(corners[0], corners[2], corners[3]),
+        ]
+    }
+
+    /// A plate on the bed with a shelf hanging above it
+    fn shelf(max_x: f64) -> Mesh {
+        let mut triangles = quad((0.0, 0.0), (10.0, 10.0), 0.0, true);
+        triangles.extend(quad((2.0, 2.0), (max_x, 4.0), 5.0, false));
+        Mesh::from(triangles)
+    }
+
+    fn settings() -> SupportSettings {
+        SupportSettings {
+            max_overhang_angle: std::f64::consts::FRAC_PI_4,
+            spacing: 2.0,
+            gap: 0.2,
+            branch_angle: std::f64::consts::FRAC_PI_4,
+            branch_diameter: 2.0,
+        }
+    }
+
+    #[test]
+    fn test_support_columns() {
+        let mesh = shelf(6.0);
+        let grid = SupportGrid::new(&mesh, 2.0);
+        let columns = support_columns(&mesh, &grid, &settings(), &vector![0.0, 0.0, 1.0]);
+
+        // The shelf covers the grid points at x = 3 and x = 5, y = 3
+        assert_eq!(
+            columns
+                .iter()
+                .map(|column| (column.x, column.y))
+                .collect::<Vec<_>>(),
+            vec![(1, 1), (2, 1)]
+        );
+        for column in columns {
+            // Resting on the plate, with a gap on both ends
+            assert_relative_eq!(column.bottom, 0.2);
+            assert_relative_eq!(column.top, 4.8);
+        }
+    }
+
+    #[test]
+    fn test_generate_support() {
+        let layers = [0.0, 1.0, 2.0, 5.0];
+        let bed_normal = vector![0.0, 0.0, 1.0];
+
+        let wide = generate_support(
+            &shelf(6.0),
+            &layers,
+            SupportPattern::Lines,
+            &settings(),
+            &bed_normal,
+        );
+        assert_eq!(wide.len(), 2);
+        assert_eq!(
+            wide[0].points,
+            vec![point![3.0, 3.0, 1.0], point![5.0, 3.0, 1.0]]
+        );
+
+        // A shelf narrower than two grid spacings still gets a line across its single cell
+        for pattern in [SupportPattern::Lines, SupportPattern::Grid] {
+            let narrow = generate_support(&shelf(4.0), &layers, pattern, &settings(), &bed_normal);
+            assert_eq!(narrow.len(), 2);
+            assert_eq!(
+                narrow[1].points,
+                vec![point![2.0, 3.0, 2.0], point![4.0, 3.0, 2.0]]
+            );
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use nalgebra::Point3;
+
+use super::FloatValue;
+
+/// What a path is printed for
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PathRole {
+    #[default]
+    OuterWall,
+    InnerWall,
+    SurfacePerimeter,
+    SurfaceFill,
+    Infill,
+    Support,
+    /// Planar lines below unsupported bottom surfaces
+    Bridge,
+    Skirt,
+    Brim,
+    Raft,
+    /// Outlines and purge lines of the wipe tower
+    WipeTower,
+    Travel,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ToolPath {
+    pub role: PathRole,
+    pub points: Vec<Point3<FloatValue>>,
+    /// The extrusion multiplier of each segment, relative to a planar layer.
+    pub flow: Vec<FloatValue>,
+    pub width: FloatValue,
+    /// Index of the planar layer the path is printed with
+    pub layer: usize,
+    /// Closed paths end where they start and keep their direction.
+    pub closed: bool,
+    /// Index of the non-planar surface the path belongs to
+    pub surface: Option<usize>,
+    /// Index of the extruder the path is printed with
+    pub extruder: usize,
+}
+
+impl ToolPath {
+    /// A path printed with a constant layer thickness
+    pub fn planar(points: Vec<Point3<FloatValue>>, flow: FloatValue, closed: bool) -> Self {
+        Self {
+            flow: vec![flow; points.len().saturating_sub(1)],
+            points,
+            closed,
+            ..Default::default()
+        }
+    }
+
+    pub fn reverse(&mut self) {
+        self.points.reverse();
+        self.flow.reverse();
+    }
+}
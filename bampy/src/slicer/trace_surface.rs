@@ -10,6 +10,13 @@ use super::{
     FloatValue,
 };
 
+/// The steepest surface the toolhead can reach when printing with the maximum angle `a`.
+/// A surface rising more steeply than `π/2 - a` enters the cone `trace_surface` checks
+/// the toolhead with. Surfaces up to `a` are printed non-planar either way.
+pub fn max_reachable_angle(a: FloatValue) -> FloatValue {
+    (std::f64::consts::FRAC_PI_2 - a).max(a)
+}
+
 pub fn trace_surface(point: &Point3<FloatValue>, surface: &Mesh, a: FloatValue) -> bool {
     let sdf = SdfInfiniteCone::new(a);
     let mut stack = Vec::<usize>::new();
@@ -0,0 +1,440 @@
+use std::collections::HashSet;
+
+use nalgebra::{point, vector, Point2, Point3, Vector2, Vector3};
+
+use super::{
+    aabb_from_points,
+    axis::Axis,
+    mesh::Mesh,
+    slice_path::SlicePath,
+    support::{support_columns, vertical_hits, SupportGrid, SupportSettings},
+    trace_surface::trace_surface,
+    FloatValue,
+};
+
+/// Number of segments of the ring printed for a branch
+const BRANCH_SEGMENTS: usize = 8;
+/// Upper bound of the radius of merged branches, relative to a single branch
+const MAX_BRANCH_SCALE: FloatValue = 3.0;
+
+#[derive(Debug, Clone, Copy)]
+struct Branch {
+    position: Point2<FloatValue>,
+    /// The number of contact points merged into this branch
+    weight: usize,
+    /// The contact this branch grew from, or one of the contacts merged into it
+    contact: usize,
+}
+
+impl Branch {
+    fn radius(&self, settings: &SupportSettings) -> FloatValue {
+        settings.branch_diameter / 2.0 * (self.weight as FloatValue).sqrt().min(MAX_BRANCH_SCALE)
+    }
+
+    /// A single branch at the weighted average position of both
+    fn join(&self, other: &Branch) -> Branch {
+        let weight = self.weight + other.weight;
+        Branch {
+            position: Point2::from(
+                (self.position.coords * self.weight as FloatValue
+                    + other.position.coords * other.weight as FloatValue)
+                    / weight as FloatValue,
+            ),
+            weight,
+            contact: self.contact,
+        }
+    }
+}
+
+/// Follows the contacts merged into others down to the one that kept growing
+fn merged_into(merges: &[usize], mut contact: usize) -> usize {
+    while merges[contact] != contact {
+        contact = merges[contact];
+    }
+    contact
+}
+
+struct TreeSupport<'a> {
+    mesh: &'a Mesh,
+    surfaces: &'a [&'a Mesh],
+    max_angle: FloatValue,
+    settings: &'a SupportSettings,
+}
+
+impl TreeSupport<'_> {
+    /// Checks if a point lies inside the model, by counting the mesh crossings above it
+    fn inside_model(&self, point: &Point3<FloatValue>) -> bool {
+        vertical_hits(self.mesh, point.x, point.y)
+            .iter()
+            .filter(|(z, _)| *z > point.z)
+            .count()
+            % 2
+            == 1
+    }
+
+    /// The top of the model or the bed below a point
+    fn ground(&self, point: &Point3<FloatValue>) -> FloatValue {
+        vertical_hits(self.mesh, point.x, point.y)
+            .iter()
+            .rev()
+            .find(|(z, normal)| *z < point.z && *normal > 0.0)
+            .map(|(z, _)| *z + self.settings.gap)
+            .unwrap_or(self.mesh.aabb.min.z)
+    }
+
+    /// Checks if a branch can be printed at the given position,
+    /// without touching the model or the toolhead of a surface printed afterwards.
+    ///
+    /// Like the dependency resolution, only surfaces that are pending at that height
+    /// are checked, the ones below it are printed before the support reaches them.
+    fn is_valid(&self, position: &Point2<FloatValue>, radius: FloatValue, z: FloatValue) -> bool {
+        let samples = [
+            vector![0.0, 0.0],
+            vector![radius, 0.0],
+            vector![-radius, 0.0],
+            vector![0.0, radius],
+            vector![0.0, -radius],
+        ];
+        samples.iter().all(|offset| {
+            let point = point![position.x + offset.x, position.y + offset.y, z];
+            !self.inside_model(&point)
+                && self
+                    .surfaces
+                    .iter()
+                    .filter(|surface| surface.aabb.min.z <= z && z <= surface.aabb.max.z)
+                    .all(|surface| trace_surface(&point, surface, self.max_angle))
+        })
+    }
+
+    /// Moves a branch towards the target as far as the branch angle allows,
+    /// avoiding invalid positions by trying to move around them.
+    /// Returns `None` if the branch has no valid position left.
+    fn step(
+        &self,
+        branch: &Branch,
+        target: Point2<FloatValue>,
+        max_move: FloatValue,
+        z: FloatValue,
+    ) -> Option<Point2<FloatValue>> {
+        let radius = branch.radius(self.settings);
+        let delta = target - branch.position;
+        let desired = if delta.norm() > max_move {
+            branch.position + delta.normalize() * max_move
+        } else {
+            target
+        };
+        if self.is_valid(&desired, radius, z) {
+            return Some(desired);
+        }
+
+        (0..BRANCH_SEGMENTS)
+            .map(|i| {
+                let angle = i as FloatValue / BRANCH_SEGMENTS as FloatValue * std::f64::consts::TAU;
+                branch.position + Vector2::new(angle.cos(), angle.sin()) * max_move
+            })
+            .chain(std::iter::once(branch.position))
+            .filter(|candidate| self.is_valid(candidate, radius, z))
+            .min_by(|a, b| {
+                (a - desired)
+                    .norm()
+                    .partial_cmp(&(b - desired).norm())
+                    .unwrap()
+            })
+    }
+}
+
+fn branch_ring(branch: &Branch, radius: FloatValue, i: usize, d: FloatValue) -> SlicePath {
+    // clockwise, like the rings of the walls
+    let mut points = (0..BRANCH_SEGMENTS)
+        .map(|k| {
+            let angle = -(k as FloatValue) / BRANCH_SEGMENTS as FloatValue * std::f64::consts::TAU;
+            point![
+                branch.position.x + angle.cos() * radius,
+                branch.position.y + angle.sin() * radius,
+                d
+            ]
+        })
+        .collect::<Vec<_>>();
+    points.push(points[0]);
+    SlicePath {
+        i,
+        d,
+        axis: Axis::Z,
+        closed: true,
+        aabb: aabb_from_points(points.iter()),
+        points,
+    }
+}
+
+/// Generates tree supports, growing branches from the overhangs towards the bed.
+///
+/// Branches lean towards their neighbours to merge, at most by the branch angle,
+/// and are routed around the model and around the toolhead keep-out volume
+/// of every non-planar surface that is printed after them.
+/// A branch that can't be routed down to the bed or the model would be left floating,
+/// so the whole tree it belongs to is dropped instead.
+/// Returns the support paths and the contacts of the dropped trees.
+pub fn generate_tree_support(
+    mesh: &Mesh,
+    layers: &[FloatValue],
+    surfaces: &[&Mesh],
+    max_angle: FloatValue,
+    settings: &SupportSettings,
+    bed_normal: &Vector3<FloatValue>,
+) -> (Vec<SlicePath>, Vec<Point3<FloatValue>>) {
+    let tree = TreeSupport {
+        mesh,
+        surfaces,
+        max_angle,
+        settings,
+    };
+    let grid = SupportGrid::new(mesh, settings.spacing);
+    let mut contacts = support_columns(mesh, &grid, settings, bed_normal);
+    contacts.sort_unstable_by(|a, b| a.top.partial_cmp(&b.top).unwrap());
+
+    let merge_distance = settings.branch_diameter / 2.0;
+    let attract_distance = settings.spacing * 2.0;
+    let mut branches = Vec::<Branch>::new();
+    // The contact points, and the contact each of them was merged into
+    let mut points = Vec::new();
+    let mut merges = Vec::new();
+    let mut dropped = Vec::new();
+    let mut paths = Vec::new();
+    for (i, layer) in layers.iter().enumerate().rev() {
+        while contacts.last().is_some_and(|contact| contact.top >= *layer) {
+            let contact = contacts.pop().unwrap();
+            let branch = Branch {
+                position: point![grid.x(contact.x), grid.y(contact.y)],
+                weight: 1,
+                contact: points.len(),
+            };
+            points.push(point![branch.position.x, branch.position.y, contact.top]);
+            merges.push(branch.contact);
+            if tree.is_valid(&branch.position, branch.radius(settings), *layer) {
+                branches.push(branch);
+            } else {
+                dropped.push(branch.contact);
+            }
+        }
+
+        branches.retain(|branch| {
+            let position = point![branch.position.x, branch.position.y, *layer];
+            tree.ground(&position) < *layer
+        });
+        for branch in &branches {
+            paths.push((
+                branch.contact,
+                branch_ring(branch, branch.radius(settings), i, *layer),
+            ));
+        }
+
+        let Some(next) = i.checked_sub(1).map(|i| layers[i]) else {
+            break;
+        };
+        let max_move = (layer - next) * settings.branch_angle.tan();
+        let targets = branches
+            .iter()
+            .enumerate()
+            .map(|(j, branch)| {
+                branches
+                    .iter()
+                    .enumerate()
+                    .filter(|(k, _)| *k != j)
+                    .map(|(_, other)| other.position)
+                    .filter(|other| (other - branch.position).norm() < attract_distance)
+                    .min_by(|a, b| {
+                        (a - branch.position)
+                            .norm()
+                            .partial_cmp(&(b - branch.position).norm())
+                            .unwrap()
+                    })
+                    .map(|other| branch.position + (other - branch.position) / 2.0)
+                    .unwrap_or(branch.position)
+            })
+            .collect::<Vec<_>>();
+        let moved = branches
+            .iter()
+            .zip(targets)
+            .filter_map(|(branch, target)| {
+                let position = tree.step(branch, target, max_move, next);
+                if position.is_none() {
+                    dropped.push(branch.contact);
+                }
+                position.map(|position| Branch {
+                    position,
+                    ..*branch
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Branches only merge where the thicker branch fits
+        let mut merged = Vec::<Branch>::with_capacity(moved.len());
+        for branch in moved {
+            let joined = merged
+                .iter_mut()
+                .find(|other| (other.position - branch.position).norm() < merge_distance)
+                .map(|other| {
+                    let joined = other.join(&branch);
+                    (other, joined)
+                })
+                .filter(|(_, joined)| {
+                    tree.is_valid(&joined.position, joined.radius(settings), next)
+                });
+            match joined {
+                Some((other, joined)) => {
+                    merges[branch.contact] = joined.contact;
+                    *other = joined;
+                }
+                None => merged.push(branch),
+            }
+        }
+        branches = merged;
+    }
+
+    let dropped = dropped
+        .into_iter()
+        .map(|contact| merged_into(&merges, contact))
+        .collect::<HashSet<_>>();
+    let mut paths = paths
+        .into_iter()
+        .filter(|(contact, _)| !dropped.contains(&merged_into(&merges, *contact)))
+        .map(|(_, path)| path)
+        .collect::<Vec<_>>();
+    paths.reverse();
+    let unsupported = (0..points.len())
+        .filter(|contact| dropped.contains(&merged_into(&merges, *contact)))
+        .map(|contact| points[contact])
+        .collect();
+    (paths, unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{point, vector, Point3};
+
+    use crate::slicer::{
+        mesh::Mesh,
+        slice_path::SlicePath,
+        support::SupportSettings,
+        tree_support::{generate_tree_support, TreeSupport},
+        triangle::Triangle,
+    };
+
+    /// The closed triangles of an axis aligned box, facing outwards
+    fn cuboid(min: Point3<f64>, max: Point3<f64>) -> Vec<Triangle> {
+        let c = |x: usize, y: usize, z: usize| {
+            point![[min.x, max.x][x], [min.y, max.y][y], [min.z, max.z][z]]
+        };
+        [
+            [c(0, 0, 0), c(0, 1, 0), c(1, 1, 0), c(1, 0, 0)],
+            [c(0, 0, 1), c(1, 0, 1), c(1, 1, 1), c(0, 1, 1)],
+            [c(0, 0, 0), c(1, 0, 0), c(1, 0, 1), c(0, 0, 1)],
+            [c(0, 1, 0), c(0, 1, 1), c(1, 1, 1), c(1, 1, 0)],
+            [c(0, 0, 0), c(0, 0, 1), c(0, 1, 1), c(0, 1, 0)],
+            [c(1, 0, 0), c(1, 1, 0), c(1, 1, 1), c(1, 0, 1)],
+        ]
+        .iter()
+        .flat_map(|[a, b, c, d]| [Triangle::new(*a, *b, *c), Triangle::new(*a, *c, *d)])
+        .collect()
+    }
+
+    fn settings() -> SupportSettings {
+        SupportSettings {
+            max_overhang_angle: std::f64::consts::FRAC_PI_4,
+            spacing: 2.0,
+            gap: 0.2,
+            branch_angle: std::f64::consts::FRAC_PI_4,
+            branch_diameter: 2.0,
+        }
+    }
+
+    fn layers() -> Vec<f64> {
+        (0..=25).map(|i| i as f64 * 0.2).collect()
+    }
+
+    #[test]
+    fn test_branches_avoid_model() {
+        // Two branches below a shelf, merging right above a thin pillar
+        let mut triangles = cuboid(point![2.0, 2.0, 5.0], point![6.0, 4.0, 6.0]);
+        triangles.extend(cuboid(point![3.8, 0.0, 0.0], point![4.2, 6.0, 3.0]));
+        let mesh = Mesh::from(triangles);
+        let settings = settings();
+
+        let (paths, unsupported) = generate_tree_support(
+            &mesh,
+            &layers(),
+            &[],
+            0.5,
+            &settings,
+            &vector![0.0, 0.0, 1.0],
+        );
+        assert!(!paths.is_empty());
+        assert!(unsupported.is_empty());
+        let tree = TreeSupport {
+            mesh: &mesh,
+            surfaces: &[],
+            max_angle: 0.5,
+            settings: &settings,
+        };
+        for path in &paths {
+            for point in &path.points {
+                assert!(!tree.inside_model(point), "{:?} is inside the model", point);
+            }
+        }
+    }
+
+    #[test]
+    fn test_branches_avoid_toolhead() {
+        let mut triangles = cuboid(point![2.0, 2.0, 5.0], point![4.0, 4.0, 6.0]);
+        triangles.extend(cuboid(point![12.0, 12.0, 5.0], point![14.0, 14.0, 6.0]));
+        triangles.extend(cuboid(point![0.0, 0.0, 0.0], point![16.0, 16.0, 0.2]));
+        let mesh = Mesh::from(triangles);
+        // A surface printed after the support, rising from right below the first shelf
+        let surface = Mesh::from(vec![Triangle::new(
+            point![3.0, 3.0, 0.5],
+            point![20.0, 0.0, 4.0],
+            point![20.0, 6.0, 4.0],
+        )]);
+        let settings = settings();
+        let layers = layers();
+
+        let (paths, unsupported) = generate_tree_support(
+            &mesh,
+            &layers,
+            &[&surface],
+            0.5,
+            &settings,
+            &vector![0.0, 0.0, 1.0],
+        );
+        // The first shelf can't be supported through the keep-out volume of the surface
+        assert!(!unsupported.is_empty());
+        assert!(unsupported
+            .iter()
+            .all(|point| point.x < 5.0 && point.y < 5.0));
+        assert!(!paths.is_empty());
+
+        // Every ring stands on the bed, the model or a ring of the layer below
+        let tree = TreeSupport {
+            mesh: &mesh,
+            surfaces: &[&surface],
+            max_angle: 0.5,
+            settings: &settings,
+        };
+        let center = |path: &SlicePath| {
+            let sum = path.points[..path.points.len() - 1]
+                .iter()
+                .fold(vector![0.0, 0.0, 0.0], |sum, point| sum + point.coords);
+            Point3::from(sum / (path.points.len() - 1) as f64)
+        };
+        for path in &paths {
+            let position = center(path);
+            let grounded = tree.ground(&position) >= path.d - 0.2 - 1e-9;
+            let carried = path.i > 0
+                && paths.iter().any(|below| {
+                    below.i == path.i - 1
+                        && (center(below).xy() - position.xy()).norm() < settings.branch_diameter
+                });
+            assert!(grounded || carried, "{:?} is floating", position);
+        }
+    }
+}
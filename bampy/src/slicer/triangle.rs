@@ -5,7 +5,7 @@ use bvh::{
 };
 use nalgebra::{Point3, Vector3};
 
-use super::{line::Line3, FloatValue};
+use super::{line::Line3, paint::TriangleAttributes, FloatValue};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Triangle {
@@ -13,6 +13,7 @@ pub struct Triangle {
     pub b: Point3<FloatValue>,
     pub c: Point3<FloatValue>,
     pub normal: Vector3<FloatValue>,
+    pub attributes: TriangleAttributes,
     node_index: usize,
     pub aabb: Aabb<FloatValue, 3>,
 }
@@ -27,23 +28,16 @@ impl Triangle {
             b,
             c,
             normal: (b - a).cross(&(c - a)).normalize(),
+            attributes: TriangleAttributes::default(),
             node_index: 0,
             aabb,
         }
     }
 
-    pub fn has_point_in_aabb(&self, aabb: &Aabb<FloatValue, 3>) -> bool {
-        aabb.contains(&self.a) || aabb.contains(&self.b) || aabb.contains(&self.c)
-    }
-
     pub fn has_point(&self, vec: Point3<FloatValue>) -> bool {
         relative_eq!(self.a, vec) || relative_eq!(self.b, vec) || relative_eq!(self.c, vec)
     }
 
-    pub fn shares_point_with_triangle(&self, other: Triangle) -> bool {
-        self.has_point(other.a) || self.has_point(other.b) || self.has_point(other.c)
-    }
-
     pub fn intersect(&self, value: FloatValue, axis: usize) -> Option<Line3> {
         let mut intersection = Vec::<Point3<FloatValue>>::with_capacity(3);
         let mut last = &self.c;
@@ -75,6 +69,38 @@ impl Triangle {
         }
     }
 
+    /// The height of the triangle at the given XY position,
+    /// if the position lies within the projection of the triangle onto the XY plane.
+    pub fn z_at(&self, x: FloatValue, y: FloatValue) -> Option<FloatValue> {
+        let ab = self.b - self.a;
+        let ac = self.c - self.a;
+        let det = ab.x * ac.y - ac.x * ab.y;
+        if det.abs() < FloatValue::EPSILON {
+            return None;
+        }
+        let px = x - self.a.x;
+        let py = y - self.a.y;
+        let u = (px * ac.y - ac.x * py) / det;
+        let v = (ab.x * py - px * ab.y) / det;
+        if u < 0.0 || v < 0.0 || u + v > 1.0 {
+            None
+        } else {
+            Some(self.a.z + u * ab.z + v * ac.z)
+        }
+    }
+
+    /// Checks if a point lies on the triangle, within a distance of `tolerance`
+    pub fn touches(&self, point: &Point3<FloatValue>, tolerance: FloatValue) -> bool {
+        if (point - self.a).dot(&self.normal).abs() > tolerance {
+            return false;
+        }
+        [(self.a, self.b), (self.b, self.c), (self.c, self.a)]
+            .iter()
+            .all(|(p, q)| {
+                (q - p).cross(&(point - p)).dot(&self.normal) >= -tolerance * (q - p).norm()
+            })
+    }
+
     pub fn area(&self) -> FloatValue {
         let ab = self.b - self.a;
         let ac = self.c - self.a;
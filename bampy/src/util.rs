@@ -1,5 +1,7 @@
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::wasm_bindgen;
 
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
@@ -12,7 +14,29 @@ extern "C" {
     pub fn log_many(a: &str, b: &str);
 }
 
+/// Native builds, like the tests, have no console to log to.
+/// Debug builds log to stderr instead, stdout belongs to whoever embeds the library.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn log(s: &str) {
+    if cfg!(debug_assertions) {
+        eprintln!("{}", s);
+    }
+}
+
 #[macro_export]
 macro_rules! console_log {
     ($($t:tt)*) => (crate::util::log(&format_args!($($t)*).to_string()))
 }
+
+/// Iterates over a collection in parallel with the `parallel` feature, and serially otherwise.
+/// Both produce their items in the same order.
+#[macro_export]
+macro_rules! maybe_par_iter {
+    ($collection:expr) => {{
+        #[cfg(feature = "parallel")]
+        let iter = rayon::iter::IntoParallelIterator::into_par_iter($collection);
+        #[cfg(not(feature = "parallel"))]
+        let iter = IntoIterator::into_iter($collection);
+        iter
+    }};
+}
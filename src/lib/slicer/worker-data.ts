@@ -1,6 +1,9 @@
 import type { Vector3Tuple } from 'three';
+import type { Slice, SliceResult, SliceStage } from 'bampy';
 
 export interface SliceArguments {
+	/** Tags the messages of this job, so the ones of superseded jobs can be dropped */
+	job: number;
 	stl: object;
 	bedNormal: Vector3Tuple;
 	maxNonPlanarAngle: number;
@@ -8,6 +11,8 @@ export interface SliceArguments {
 	layerHeight: number;
 	nozzleDiameter: number;
 	minSurfacePathLength: number;
+	/** Shared flag that cancels the job once set to a non-zero value */
+	signal?: Int32Array;
 }
 
 export interface SliceEvent {
@@ -17,19 +22,29 @@ export interface SliceEvent {
 
 export type WorkerEvent = SliceEvent;
 
-export type WorkerMessage = LayerMessage | ProgressMessage;
+export type WorkerMessage = LayerMessage | ProgressMessage | ResultMessage;
 
 export interface LayerMessage {
 	type: 'layer';
-	data: Layer;
+	job: number;
+	dependency: number;
+	data: Slice[];
 }
 
 export interface ProgressMessage {
 	type: 'progress';
+	job: number;
+	stage: SliceStage;
 	percent?: number;
 	layer: number;
 }
 
+export interface ResultMessage {
+	type: 'result';
+	job: number;
+	data: SliceResult;
+}
+
 export interface Layer {
 	type: LayerType;
 	geometry: object;
@@ -4,9 +4,10 @@ import {
 	type ProgressMessage,
 	type WorkerEvent,
 	type LayerMessage,
+	type ResultMessage,
 	LayerType
 } from './worker-data';
-import init, { slice } from 'bampy';
+import init, { SliceJob } from 'bampy';
 
 addEventListener('message', async (event: MessageEvent<WorkerEvent>) => {
 	if (event.data.type === 'slice') {
@@ -15,16 +16,47 @@ addEventListener('message', async (event: MessageEvent<WorkerEvent>) => {
 			geometry.toNonIndexed();
 		}
 		await init();
-		const result = slice({
-			positions: geometry.attributes.position.array as Float32Array,
-			layerHeight: event.data.data.layerHeight,
-			maxAngle: event.data.data.maxNonPlanarAngle,
-			nozzleDiameter: event.data.data.nozzleDiameter,
-			minSurfacePathLength: event.data.data.minSurfacePathLength
-		});
-		self.postMessage({
-			type: 'result',
-			data: result
-		});
+		const id = event.data.data.job;
+		const job = new SliceJob(event.data.data.signal);
+		try {
+			const result = job.slice(
+				{
+					positions: geometry.attributes.position.array as Float32Array,
+					layerHeight: event.data.data.layerHeight,
+					maxAngle: event.data.data.maxNonPlanarAngle,
+					nozzleDiameter: event.data.data.nozzleDiameter,
+					minSurfacePathLength: event.data.data.minSurfacePathLength,
+					buildDirection: event.data.data.bedNormal
+				},
+				(progress) => {
+					if (progress.type === 'progress') {
+						self.postMessage({
+							type: 'progress',
+							job: id,
+							stage: progress.stage,
+							percent: progress.percent ?? undefined,
+							layer: progress.layer
+						} satisfies ProgressMessage);
+					} else {
+						self.postMessage({
+							type: 'layer',
+							job: id,
+							dependency: progress.dependency,
+							data: progress.slices
+						} satisfies LayerMessage);
+					}
+				}
+			);
+			self.postMessage({
+				type: 'result',
+				job: id,
+				data: result
+			} satisfies ResultMessage);
+		} catch (error) {
+			// A newer slice superseded this one
+			if (!job.cancelled) throw error;
+		} finally {
+			job.free();
+		}
 	}
 });